@@ -1,10 +1,11 @@
 use backend::*;
 use record::InodeUpdate;
+use attributes::FileAttributes;
 use error::Error;
 use patch::*;
 use fs_representation::{patches_dir};
 use std::collections::{HashSet, HashMap};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use rand;
 use std::mem::swap;
 
@@ -50,6 +51,7 @@ impl<'env, T: rand::Rng> MutTxn<'env, T> {
 
         assert!(self.put_patches(&mut branch.patches, patch_id, timestamp)?);
         assert!(self.put_revpatches(&mut branch.revpatches, timestamp, patch_id)?);
+        self.clear_stamps()?;
 
         debug!("apply_raw");
         let mut parents: Vec<Key<PatchId>> = Vec::new();
@@ -1001,14 +1003,16 @@ impl<'env, T: rand::Rng> MutTxn<'env, T> {
     /// Assumes all patches have been downloaded. The third argument
     /// `remote_patches` needs to contain at least all the patches we want
     /// to apply, and the fourth one `local_patches` at least all the patches the other
-    /// party doesn't have.
+    /// party doesn't have. `defaults` are the attributes of the files
+    /// of the working copy not set by the attributes file.
     pub fn apply_patches(&mut self,
                          branch_name: &str,
                          r: &Path,
-                         remote_patches: &HashMap<Hash, Patch>)
+                         remote_patches: &HashMap<Hash, Patch>,
+                         defaults: &FileAttributes)
                          -> Result<(), Error> {
         let (pending, local_pending) = {
-            let (changes, local) = try!(self.record(branch_name, &r));
+            let (changes, local) = try!(self.record(branch_name, &r, &[], false, defaults));
             let mut p = Patch::empty();
             p.changes = changes.into_iter().flat_map(|x| x.into_iter()).collect();
             (p, local)
@@ -1025,7 +1029,7 @@ impl<'env, T: rand::Rng> MutTxn<'env, T> {
             try!(self.output_changes_file(&branch, r));
             self.commit_branch(branch)?;
             debug!("output_repository");
-            try!(self.output_repository(branch_name, &r, &pending, &local_pending));
+            try!(self.output_repository(branch_name, &r, &pending, &local_pending, defaults));
             debug!("done outputting_repository");
         } else {
             // The branch needs to be committed in all cases to avoid
@@ -1206,3 +1210,163 @@ impl<'env, T: rand::Rng> MutTxn<'env, T> {
         Ok(())
     }
 }
+
+impl<'env, T: rand::Rng> MutTxn<'env, T> {
+    /// Applies `stash`, a patch whose hash is `hash` and which is
+    /// applied to no branch, to the working copy of branch
+    /// `branch_name`. Its changes are merged with the unrecorded
+    /// changes of the working copy, and stay unrecorded.
+    pub fn apply_stash(&mut self,
+                       branch_name: &str,
+                       working_copy: &Path,
+                       hash: &Hash,
+                       stash: &Patch,
+                       defaults: &FileAttributes)
+                       -> Result<(), Error> {
+        {
+            let branch = self.open_branch(branch_name)?;
+            let applied = match self.get_internal(hash.as_ref()) {
+                Some(internal) => self.get_patch(&branch.patches, &internal).is_some(),
+                None => false,
+            };
+            let missing = stash.dependencies.iter()
+                .find(|dep| match self.get_internal(dep.as_ref()) {
+                    Some(dep_internal) => self.get_patch(&branch.patches, &dep_internal).is_none(),
+                    None => true,
+                })
+                .cloned();
+            self.commit_branch(branch)?;
+            if applied {
+                return Err(Error::AlreadyApplied)
+            }
+            if let Some(dep) = missing {
+                return Err(Error::MissingDependency(dep))
+            }
+        }
+
+        // Apply the stash like any other patch, merging it with the
+        // unrecorded changes, and then unrecord it, leaving its
+        // changes in the working copy only.
+        let mut patches = HashMap::new();
+        patches.insert(hash.clone(), stash.clone());
+        self.apply_patches(branch_name, working_copy, &patches, defaults)?;
+        let internal = self.get_internal(hash.as_ref()).unwrap().to_owned();
+        let mut touched = HashSet::new();
+        for change in stash.changes.iter() {
+            match *change {
+                Change::NewNodes { ref up_context, ref down_context, .. } => {
+                    for c in up_context.iter().chain(down_context.iter()) {
+                        touched.insert(self.internal_key(c, &internal));
+                    }
+                }
+                Change::NewEdges { ref edges, .. } => {
+                    for e in edges {
+                        touched.insert(self.internal_key(&e.from, &internal));
+                        touched.insert(self.internal_key(&e.to, &internal));
+                    }
+                }
+            }
+        }
+        let added: Vec<(Inode, Key<PatchId>)> = self.iter_inodes(None)
+            .filter(|&(_, header)| header.key.patch == internal)
+            .map(|(inode, header)| (inode.to_owned(), header.key.clone()))
+            .collect();
+        let mut branch = self.open_branch(branch_name)?;
+        self.unrecord(&mut branch, &internal, stash)?;
+
+        // Now make the inodes agree with the working copy: files
+        // added by the stash become unrecorded additions, files moved
+        // by the stash are marked as moved, and files deleted by the
+        // stash are marked as deleted.
+        for (inode, key) in added {
+            self.del_inodes(&inode, None)?;
+            self.del_revinodes(&key, None)?;
+        }
+        self.mark_moved_files(&branch, &touched)?;
+        self.mark_deleted_files(&branch, &ROOT_KEY, &ROOT_INODE)?;
+        self.output_changes_file(&branch, working_copy)?;
+        self.commit_branch(branch)?;
+        Ok(())
+    }
+
+    /// Path of `inode` in the tree, relative to the root of the
+    /// repository.
+    fn tree_path(&self, inode: &Inode) -> Option<PathBuf> {
+        let mut components = Vec::new();
+        let mut current = inode.clone();
+        while current != ROOT_INODE {
+            match self.get_revtree(&current) {
+                Some(file_id) => {
+                    components.push(file_id.basename.as_str().to_string());
+                    current = file_id.parent_inode.clone();
+                }
+                None => return None,
+            }
+        }
+        Some(components.iter().rev().collect())
+    }
+
+    /// Marks the files of `candidates` whose path in the tree differs
+    /// from their path in `branch` as moved.
+    fn mark_moved_files(&mut self,
+                        branch: &Branch,
+                        candidates: &HashSet<Key<PatchId>>)
+                        -> Result<(), Error> {
+        let moved: Vec<(Inode, FileHeader)> = self.iter_inodes(None)
+            .filter(|&(inode, header)| {
+                header.status == FileStatus::Ok && candidates.contains(&header.key) &&
+                    self.tree_path(inode) != self.node_path(branch, &header.key)
+            })
+            .map(|(inode, header)| (inode.to_owned(), header.to_owned()))
+            .collect();
+        for (inode, mut header) in moved {
+            header.status = FileStatus::Moved;
+            self.replace_inodes(&inode, &header)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the files of `branch` that are missing from the tree back
+    /// to the tree, marked as deleted.
+    fn mark_deleted_files(&mut self,
+                          branch: &Branch,
+                          key: &Key<PatchId>,
+                          inode: &Inode)
+                          -> Result<(), Error> {
+        let children: Vec<(Key<PatchId>, FileMetadata, String)> =
+            self.list_files_under_node(branch, key)
+                .into_iter()
+                .map(|(k, names)| (k, names[0].0, names[0].1.to_string()))
+                .collect();
+        for (child_key, meta, basename) in children {
+            match self.get_revinodes(&child_key).map(|i| i.to_owned()) {
+                Some(child_inode) => {
+                    if meta.is_dir() {
+                        self.mark_deleted_files(branch, &child_key, &child_inode)?
+                    }
+                }
+                None => {
+                    let file_id = OwnedFileId {
+                        parent_inode: inode.clone(),
+                        basename: SmallString::from_str(&basename),
+                    };
+                    if self.get_tree(&file_id.as_file_id()).is_some() {
+                        // Another file has taken this name.
+                        continue
+                    }
+                    let child_inode = self.create_new_inode();
+                    let header = FileHeader {
+                        metadata: meta,
+                        status: FileStatus::Deleted,
+                        key: child_key.clone(),
+                    };
+                    self.put_tree(&file_id.as_file_id(), &child_inode)?;
+                    self.put_revtree(&child_inode, &file_id.as_file_id())?;
+                    self.replace_inodes(&child_inode, &header)?;
+                    self.replace_revinodes(&child_key, &child_inode)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+//! Attributes files, setting properties of the files matched by a
+//! pattern.
+//!
+//! The file `.pijulattributes`, at the root of the working copy, has
+//! one rule per line: a pattern, with the same syntax as in ignore
+//! files (negated patterns excepted), followed by attributes
+//! separated by spaces. Blank lines and lines starting with `#` are
+//! skipped. The attributes are:
+//!
+//! - `text` and `binary`: whether the file is split in lines, or in
+//!   chunks. By default, files with a NUL byte in their first 8000
+//!   bytes are binary.
+//! - `eol=auto`, `eol=lf`, `eol=crlf` and `eol=none`: the end-of-line
+//!   policy of text files (see `EolPolicy`).
+//! - `diff=myers` and `diff=patience`: how lines are aligned when
+//!   recording changes to the file.
+//! - `merge=markers` and `merge=union`: whether the sides of a
+//!   conflict are written out between conflict markers, or one after
+//!   the other without markers. Conflicts in binary files are
+//!   written out with one of their sides only.
+//!
+//! When several rules match a file, later rules take precedence over
+//! earlier ones, and over the defaults given when loading the file.
+//! Unknown attributes are ignored.
+
+use std::path::{Path, PathBuf};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::io;
+use error::Error;
+use ignore::Pattern;
+use optimal_diff::DiffAlgorithm;
+
+pub const ATTRIBUTES_FILE_NAME: &'static str = ".pijulattributes";
+
+/// Conversion of the line endings of text files. Except with
+/// `None`, line endings are recorded as LF, so that changing them in
+/// the working copy does not change the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EolPolicy {
+    /// Written out with the line endings of the platform.
+    #[serde(rename = "auto")]
+    Auto,
+    /// Written out with LF.
+    #[serde(rename = "lf")]
+    Lf,
+    /// Written out with CRLF.
+    #[serde(rename = "crlf")]
+    Crlf,
+    /// Recorded and written out unchanged.
+    #[serde(rename = "none")]
+    None,
+}
+
+/// How conflicts are written out to the working copy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Conflict sides between conflict markers.
+    Markers,
+    /// All sides one after the other, without markers.
+    Union,
+}
+
+/// The attributes of a single file. Unset attributes are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileAttributes {
+    pub text: Option<bool>,
+    pub eol: Option<EolPolicy>,
+    pub diff: Option<DiffAlgorithm>,
+    pub merge: Option<MergeStrategy>,
+}
+
+impl FileAttributes {
+    /// Sets the attribute `attribute`, as written in an attributes
+    /// file. Returns `false` if it is unknown.
+    fn set(&mut self, attribute: &str) -> bool {
+        match attribute {
+            "text" => self.text = Some(true),
+            "binary" => self.text = Some(false),
+            "eol=auto" => self.eol = Some(EolPolicy::Auto),
+            "eol=lf" => self.eol = Some(EolPolicy::Lf),
+            "eol=crlf" => self.eol = Some(EolPolicy::Crlf),
+            "eol=none" => self.eol = Some(EolPolicy::None),
+            "diff=myers" => self.diff = Some(DiffAlgorithm::Myers),
+            "diff=patience" => self.diff = Some(DiffAlgorithm::Patience),
+            "merge=markers" => self.merge = Some(MergeStrategy::Markers),
+            "merge=union" => self.merge = Some(MergeStrategy::Union),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Overrides the attributes of `self` set in `other`.
+    fn update(&mut self, other: &FileAttributes) {
+        if other.text.is_some() {
+            self.text = other.text
+        }
+        if other.eol.is_some() {
+            self.eol = other.eol
+        }
+        if other.diff.is_some() {
+            self.diff = other.diff
+        }
+        if other.merge.is_some() {
+            self.merge = other.merge
+        }
+    }
+
+    /// Whether a file with contents `contents` must be split in
+    /// lines.
+    pub fn is_text(&self, contents: &[u8]) -> bool {
+        match self.text {
+            Some(text) => text,
+            None => contents.iter().take(8000).all(|&c| c != 0),
+        }
+    }
+
+    /// Converts the contents of a text file from the working copy to
+    /// the form in which they are recorded.
+    pub fn normalize_eol(&self, contents: &mut Vec<u8>) {
+        match self.eol {
+            Some(EolPolicy::None) | None => return,
+            _ => {}
+        }
+        if !self.is_text(&contents[..]) {
+            return
+        }
+        let mut j = 0;
+        for i in 0..contents.len() {
+            if !(contents[i] == b'\r' && i + 1 < contents.len() && contents[i + 1] == b'\n') {
+                contents[j] = contents[i];
+                j += 1
+            }
+        }
+        contents.truncate(j)
+    }
+
+    /// Converts recorded contents of a text file to the form in which
+    /// they are written to the working copy.
+    pub fn convert_eol(&self, contents: &mut Vec<u8>) {
+        let crlf = match self.eol {
+            Some(EolPolicy::Crlf) => true,
+            Some(EolPolicy::Auto) => cfg!(windows),
+            _ => false,
+        };
+        if !crlf || !self.is_text(&contents[..]) {
+            return
+        }
+        let mut converted = Vec::with_capacity(contents.len());
+        for i in 0..contents.len() {
+            if contents[i] == b'\n' && (i == 0 || contents[i - 1] != b'\r') {
+                converted.push(b'\r')
+            }
+            converted.push(contents[i])
+        }
+        *contents = converted
+    }
+}
+
+/// The rules of the attributes file of a working copy.
+#[derive(Debug)]
+pub struct Attributes {
+    repo_root: PathBuf,
+    defaults: FileAttributes,
+    rules: Vec<(Pattern, FileAttributes)>,
+}
+
+impl Attributes {
+    /// Reads the attributes file of the working copy rooted at
+    /// `repo_root`, if there is one. Attributes not set by this file
+    /// are taken from `defaults`.
+    pub fn load<P: AsRef<Path>>(repo_root: P, defaults: &FileAttributes) -> Result<Self, Error> {
+        let repo_root = repo_root.as_ref();
+        let mut rules = Vec::new();
+        match File::open(repo_root.join(ATTRIBUTES_FILE_NAME)) {
+            Ok(f) => {
+                for line in BufReader::new(f).lines() {
+                    let line = line?;
+                    let mut words = line.split_whitespace();
+                    let pattern = match words.next().and_then(Pattern::parse) {
+                        Some(ref p) if p.is_negated() => {
+                            warn!("negated patterns are not allowed in attributes: {:?}", line);
+                            continue
+                        }
+                        Some(p) => p,
+                        None => continue,
+                    };
+                    let mut attributes = FileAttributes::default();
+                    for word in words {
+                        if !attributes.set(word) {
+                            warn!("unknown attribute {:?}", word)
+                        }
+                    }
+                    rules.push((pattern, attributes))
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::IO(e)),
+        }
+        debug!("attributes: {:?}", rules);
+        Ok(Attributes {
+            repo_root: repo_root.to_path_buf(),
+            defaults: defaults.clone(),
+            rules: rules,
+        })
+    }
+
+    pub fn repo_root(&self) -> &Path {
+        &self.repo_root
+    }
+
+    /// The attributes of file `path`, relative to the repository
+    /// root.
+    pub fn get<P: AsRef<Path>>(&self, path: P) -> FileAttributes {
+        let relative = path.as_ref().to_string_lossy().replace('\\', "/");
+        let mut attributes = self.defaults.clone();
+        for &(ref pattern, ref a) in self.rules.iter() {
+            if pattern.matches(relative.as_bytes(), false) {
+                attributes.update(a)
+            }
+        }
+        attributes
+    }
+
+    /// The attributes of file `realpath`, in the working copy.
+    pub fn get_real<P: AsRef<Path>>(&self, realpath: P) -> FileAttributes {
+        match realpath.as_ref().strip_prefix(&self.repo_root) {
+            Ok(relative) => self.get(relative),
+            Err(_) => self.defaults.clone(),
+        }
+    }
+}
@@ -647,6 +647,12 @@ pub use self::inode::*;
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
 pub struct FileMetadata(u16);
 const DIR_BIT: u16 = 0x200;
+/// File type bits of the Unix mode, which older versions stored
+/// unmodified along with the permissions.
+const FILE_TYPE_MASK: u16 = 0o170000;
+/// Unix file type of symbolic links. Older versions followed links,
+/// so this type cannot appear in their metadata.
+const SYMLINK_TYPE: u16 = 0o120000;
 use byteorder::ByteOrder;
 impl FileMetadata {
     pub fn from_contents(p: &[u8]) -> Self {
@@ -665,6 +671,13 @@ impl FileMetadata {
         m
     }
 
+    /// Metadata of a symbolic link, whose contents is the link's target.
+    pub fn new_symlink(perm: usize) -> Self {
+        let mut m = FileMetadata::new(perm, false);
+        m.set_symlink();
+        m
+    }
+
     pub fn permissions(&self) -> u16 {
         u16::from_le(self.0) & 0x1ff
     }
@@ -688,6 +701,22 @@ impl FileMetadata {
         let bits = u16::from_le(self.0);
         self.0 = (bits & !DIR_BIT).to_le()
     }
+
+    pub fn is_symlink(&self) -> bool {
+        u16::from_le(self.0) & FILE_TYPE_MASK == SYMLINK_TYPE
+    }
+
+    pub fn set_symlink(&mut self) {
+        let bits = u16::from_le(self.0);
+        self.0 = ((bits & !FILE_TYPE_MASK) | SYMLINK_TYPE).to_le()
+    }
+
+    pub fn unset_symlink(&mut self) {
+        if self.is_symlink() {
+            let bits = u16::from_le(self.0);
+            self.0 = (bits & !FILE_TYPE_MASK).to_le()
+        }
+    }
 }
 
 use byteorder::{BigEndian, WriteBytesExt};
@@ -762,6 +791,57 @@ mod file_header {
 
 pub use self::file_header::*;
 
+mod file_stamp {
+    use sanakirja::{Representable, Alignment};
+    use std;
+
+    /// What the filesystem says about a file of the working copy the
+    /// last time it was found identical to its recorded version. If
+    /// the file still has the same stamp, it need not be read again.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    #[repr(packed)]
+    pub struct FileStamp {
+        /// Hash of the name of the branch the file was compared with.
+        pub branch: u64,
+        pub mtime: i64,
+        pub mtime_nsec: u32,
+        pub size: u64,
+        pub ino: u64,
+    }
+
+    impl Representable for FileStamp {
+        fn alignment() -> Alignment {
+            Alignment::B1
+        }
+        fn onpage_size(&self) -> u16 {
+            std::mem::size_of::<FileStamp>() as u16
+        }
+        unsafe fn write_value(&self, p: *mut u8) {
+            trace!("write_value {:?}", p);
+            std::ptr::copy(self as *const FileStamp, p as *mut FileStamp, 1)
+        }
+        unsafe fn read_value(p: *const u8) -> Self {
+            trace!("read_value {:?}", p);
+            let mut ret = FileStamp {
+                branch: 0,
+                mtime: 0,
+                mtime_nsec: 0,
+                size: 0,
+                ino: 0,
+            };
+            std::ptr::copy(p as *const FileStamp, &mut ret as *mut FileStamp, 1);
+            ret
+        }
+        unsafe fn cmp_value<T>(&self, _: &T, x: Self) -> std::cmp::Ordering {
+            self.cmp(&x)
+        }
+        type PageOffsets = std::iter::Empty<u64>;
+        fn page_offsets(&self) -> Self::PageOffsets { std::iter::empty() }
+    }
+}
+
+pub use self::file_stamp::*;
+
 mod file_id {
     use sanakirja::{Representable, Alignment};
     use std;
@@ -1051,6 +1131,10 @@ pub struct Dbs {
     revdep: sanakirja::Db<self::patch_id::PatchId, self::patch_id::PatchId>,
     /// A map from branch names to graphs.
     branches: sanakirja::Db<self::small_string::UnsafeSmallStr, (NodesDb, PatchSet, RevPatchSet, u64)>,
+    /// A map from inodes to the stamps of unchanged files. This is
+    /// `None` in read-only transactions on repositories where it was
+    /// never created.
+    stamps: Option<sanakirja::Db<self::inode::UnsafeInode, self::file_stamp::FileStamp>>,
 }
 
 
@@ -1080,6 +1164,7 @@ pub enum Root {
     External,
     RevDep,
     Branches,
+    Stamps,
 }
 
 trait OpenDb: Transaction {
@@ -1092,6 +1177,13 @@ trait OpenDb: Transaction {
             Err(Error::NoDb(num))
         }
     }
+    /// Like `open_db`, but tolerates missing databases in read-only
+    /// transactions.
+    fn open_optional_db<K: Representable, V: Representable>(&mut self,
+                                                            num: Root)
+                                                            -> Result<Option<sanakirja::Db<K, V>>, Error> {
+        Ok(self.root(num as usize))
+    }
 }
 
 impl<'a, T> OpenDb for sanakirja::MutTxn<'a, T> {
@@ -1104,6 +1196,11 @@ impl<'a, T> OpenDb for sanakirja::MutTxn<'a, T> {
             Ok(try!(self.create_db()))
         }
     }
+    fn open_optional_db<K: Representable, V: Representable>(&mut self,
+                                                            num: Root)
+                                                            -> Result<Option<sanakirja::Db<K, V>>, Error> {
+        Ok(Some(self.open_db(num)?))
+    }
 }
 impl<'a> OpenDb for sanakirja::Txn<'a> {}
 
@@ -1164,6 +1261,7 @@ impl Dbs {
         let internal = try!(txn.open_db(Root::Internal));
         let contents = try!(txn.open_db(Root::Contents));
         let revdep = try!(txn.open_db(Root::RevDep));
+        let stamps = try!(txn.open_optional_db(Root::Stamps));
 
         Ok(Dbs {
             external: external,
@@ -1175,6 +1273,7 @@ impl Dbs {
             internal: internal,
             revdep: revdep,
             contents: contents,
+            stamps: stamps,
         })
     }
 }
@@ -1272,6 +1371,9 @@ impl<'env, R: rand::Rng> MutTxn<'env, R> {
         self.txn.set_root(Root::External as usize, self.dbs.external);
         self.txn.set_root(Root::Branches as usize, self.dbs.branches);
         self.txn.set_root(Root::RevDep as usize, self.dbs.revdep);
+        if let Some(stamps) = self.dbs.stamps {
+            self.txn.set_root(Root::Stamps as usize, stamps);
+        }
 
         try!(self.txn.commit());
         Ok(())
@@ -1644,6 +1746,13 @@ impl<U: Transaction, R> T<U, R> {
         }
     }
 
+    pub fn get_stamps(&self, key: &Inode) -> Option<FileStamp> {
+        match self.dbs.stamps {
+            Some(ref stamps) => self.txn.get(stamps, key.to_unsafe(), None),
+            None => None,
+        }
+    }
+
     pub fn get_internal(&self, key: HashRef) -> Option<PatchId> {
         match key {
             HashRef::None => Some(ROOT_PATCH_ID),
@@ -1833,6 +1942,36 @@ impl<'env, R: rand::Rng> MutTxn<'env, R> {
     }
 
 
+    pub fn replace_stamps(&mut self, key: &Inode, value: &FileStamp) -> Result<bool, Error> {
+        match self.dbs.stamps {
+            Some(ref mut stamps) => {
+                self.txn.del(&mut self.rng, stamps, key.to_unsafe(), None)?;
+                Ok(self.txn.put(&mut self.rng, stamps, key.to_unsafe(), *value)?)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Forgets all stamps. This must be called whenever a branch
+    /// changes, since files unchanged with respect to the old version
+    /// of the branch might not be with respect to the new one.
+    pub fn clear_stamps(&mut self) -> Result<(), Error> {
+        let inodes: Vec<Inode> = match self.dbs.stamps {
+            Some(ref stamps) => {
+                self.txn.iter(stamps, None)
+                    .map(|(k, _)| unsafe { Inode::from_unsafe(k).to_owned() })
+                    .collect()
+            }
+            None => return Ok(()),
+        };
+        for inode in inodes.iter() {
+            if let Some(ref mut stamps) = self.dbs.stamps {
+                self.txn.del(&mut self.rng, stamps, inode.to_unsafe(), None)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn put_contents(&mut self, key: &Key<PatchId>, value: UnsafeValue) -> Result<bool, Error> {
         Ok(try!(self.txn.put(&mut self.rng,
                              &mut self.dbs.contents,
@@ -0,0 +1,57 @@
+//! Content-defined chunking of binary files. Binary files have no
+//! lines, so they are cut where a rolling hash of the last bytes
+//! matches a fixed pattern instead. Boundaries depend only on the
+//! neighbouring contents, hence an edit only changes the chunks
+//! around it, and the other chunks are matched by the diff.
+
+use std::cmp::min;
+
+/// No boundary is placed before this many bytes in a chunk.
+const MIN_CHUNK_SIZE: usize = 2048;
+
+/// A boundary is forced after this many bytes.
+const MAX_CHUNK_SIZE: usize = 65536;
+
+/// A boundary is placed where the top bits of the hash are all zero,
+/// i.e. every 8 KiB on average after the minimum size.
+const BOUNDARY_BITS: u32 = 13;
+
+/// Pseudo-random value of a byte in the rolling hash. Since the hash
+/// is shifted by one bit per byte, its top bits depend only on the
+/// last 64 bytes.
+fn gear(byte: u8) -> u64 {
+    (byte as u64 + 1).wrapping_mul(0x9e3779b97f4a7c15).rotate_left(29)
+}
+
+/// Splits `buf` into chunks, whose concatenation is `buf`.
+pub fn read_chunks(buf: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < buf.len() {
+        let end = next_boundary(&buf[start..]) + start;
+        chunks.push(&buf[start..end]);
+        start = end
+    }
+    chunks
+}
+
+/// Length of the first chunk of `buf`.
+fn next_boundary(buf: &[u8]) -> usize {
+    if buf.len() <= MIN_CHUNK_SIZE {
+        return buf.len()
+    }
+    let end = min(buf.len(), MAX_CHUNK_SIZE);
+    let mut hash = 0u64;
+    // Start hashing a window before the minimum size, so that the
+    // first possible boundary depends on a full window too.
+    for &byte in &buf[MIN_CHUNK_SIZE - 64..MIN_CHUNK_SIZE] {
+        hash = (hash << 1).wrapping_add(gear(byte))
+    }
+    for i in MIN_CHUNK_SIZE..end {
+        hash = (hash << 1).wrapping_add(gear(buf[i]));
+        if hash >> (64 - BOUNDARY_BITS) == 0 {
+            return i + 1
+        }
+    }
+    end
+}
@@ -1,5 +1,311 @@
+//! Conflicts, both as text markers in output files, and as a
+//! structured description of the conflicting lines of a branch.
+
+use backend::*;
+use graph::LineBuffer;
+use error::Error;
+use optimal_diff::{read_lines, DiffAlgorithm};
+use patch::Record;
+use rand;
+use sanakirja::value::Value;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
 pub const START_MARKER: &'static str = ">>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>\n";
 
 pub const SEPARATOR:  &'static str = "================================\n";
 
 pub const END_MARKER: &'static str = "<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<\n";
+
+/// A line involved in a conflict.
+#[derive(Debug, Clone)]
+pub struct ConflictLine {
+    /// The key of this line, whose `patch` field is the patch that
+    /// introduced it.
+    pub key: Key<PatchId>,
+    /// A zombie line is a line that was deleted by a patch, and
+    /// brought back to life by another patch that did not know about
+    /// the deletion, for instance by adding lines in its context.
+    pub is_zombie: bool,
+    pub contents: Vec<u8>,
+}
+
+/// One side of a conflict, i.e. a sequence of lines.
+#[derive(Debug, Clone)]
+pub struct Side {
+    pub lines: Vec<ConflictLine>,
+}
+
+impl Side {
+    /// The patches that introduced the lines on this side, in order
+    /// of first appearance.
+    pub fn patches(&self) -> Vec<PatchId> {
+        let mut patches = Vec::new();
+        for l in self.lines.iter() {
+            let patch = l.key.patch;
+            if !patches.contains(&patch) {
+                patches.push(patch)
+            }
+        }
+        patches
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    /// The line (starting from 1) of the start marker of this
+    /// conflict, in the file as output in the working copy.
+    pub line: usize,
+    /// The sides of this conflict. A conflict with a single side
+    /// comes from zombie lines.
+    pub sides: Vec<Side>,
+}
+
+impl Conflict {
+    pub fn has_zombies(&self) -> bool {
+        self.sides.iter().any(|s| s.lines.iter().any(|l| l.is_zombie))
+    }
+}
+
+/// The conflicts of a file.
+#[derive(Debug)]
+pub struct FileConflicts {
+    /// Path of this file, relative to the root of the repository.
+    pub path: PathBuf,
+    /// The key of the file's node in the graph.
+    pub key: Key<PatchId>,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// A `LineBuffer` recording the structure of conflicts instead of
+/// outputting a file.
+struct ConflictBuffer {
+    zombies: HashSet<Key<PatchId>>,
+    /// Current line in the output file, starting from 1.
+    line: usize,
+    current: Option<Conflict>,
+    conflicts: Vec<Conflict>,
+}
+
+impl<'a, A: Transaction + 'a> LineBuffer<'a, A> for ConflictBuffer {
+    fn output_line(&mut self, key: &Key<PatchId>, contents: Value<'a, A>) -> Result<(), Error> {
+        let mut line = Vec::new();
+        for chunk in contents {
+            line.extend(chunk)
+        }
+        if line.is_empty() {
+            return Ok(())
+        }
+        self.line += line.iter().filter(|&&c| c == b'\n').count();
+        if let Some(ref mut conflict) = self.current {
+            if let Some(side) = conflict.sides.last_mut() {
+                side.lines.push(ConflictLine {
+                    key: key.clone(),
+                    is_zombie: self.zombies.contains(key),
+                    contents: line,
+                })
+            }
+        }
+        Ok(())
+    }
+
+    fn output_conflict_marker(&mut self, _: &'a str) -> Result<(), Error> {
+        self.line += 1;
+        Ok(())
+    }
+
+    fn begin_conflict(&mut self) -> Result<(), Error> {
+        self.current = Some(Conflict {
+            line: self.line,
+            sides: vec![Side { lines: Vec::new() }],
+        });
+        self.line += 1;
+        Ok(())
+    }
+
+    fn conflict_next(&mut self) -> Result<(), Error> {
+        if let Some(ref mut conflict) = self.current {
+            conflict.sides.push(Side { lines: Vec::new() })
+        }
+        self.line += 1;
+        Ok(())
+    }
+
+    fn end_conflict(&mut self) -> Result<(), Error> {
+        if let Some(conflict) = self.current.take() {
+            self.conflicts.push(conflict)
+        }
+        self.line += 1;
+        Ok(())
+    }
+}
+
+/// A `LineBuffer` outputting a file where some of the conflicts are
+/// replaced by one of their sides.
+struct ResolveBuffer<'b> {
+    /// For each conflict of the file, the index of the side to keep,
+    /// or `None` to leave that conflict as it is.
+    choices: &'b [Option<usize>],
+    /// Index of the current conflict, and of the current side in
+    /// that conflict.
+    conflict: Option<(usize, usize)>,
+    n_conflicts: usize,
+    contents: Vec<u8>,
+}
+
+impl<'b> ResolveBuffer<'b> {
+    fn choice(&self) -> Option<usize> {
+        self.conflict.and_then(|(c, _)| self.choices.get(c).cloned().unwrap_or(None))
+    }
+
+    /// Outputs a conflict marker, unless the current conflict is
+    /// being resolved.
+    fn output_marker(&mut self, marker: &str) {
+        if self.choice().is_none() {
+            self.contents.extend(marker.as_bytes())
+        }
+    }
+}
+
+impl<'a, 'b, A: Transaction + 'a> LineBuffer<'a, A> for ResolveBuffer<'b> {
+    fn output_line(&mut self, _: &Key<PatchId>, contents: Value<'a, A>) -> Result<(), Error> {
+        let keep = match (self.conflict, self.choice()) {
+            (Some((_, side)), Some(choice)) => side == choice,
+            _ => true,
+        };
+        if keep {
+            for chunk in contents {
+                self.contents.extend(chunk)
+            }
+        }
+        Ok(())
+    }
+
+    fn output_conflict_marker(&mut self, marker: &'a str) -> Result<(), Error> {
+        self.output_marker(marker);
+        Ok(())
+    }
+
+    fn begin_conflict(&mut self) -> Result<(), Error> {
+        self.conflict = Some((self.n_conflicts, 0));
+        self.n_conflicts += 1;
+        self.output_marker(START_MARKER);
+        Ok(())
+    }
+
+    fn conflict_next(&mut self) -> Result<(), Error> {
+        if let Some((_, ref mut side)) = self.conflict {
+            *side += 1
+        }
+        self.output_marker(SEPARATOR);
+        Ok(())
+    }
+
+    fn end_conflict(&mut self) -> Result<(), Error> {
+        self.output_marker(END_MARKER);
+        self.conflict = None;
+        Ok(())
+    }
+}
+
+impl<A: Transaction, R> T<A, R> {
+    /// The conflicts of the file whose node is `key`, in the order in
+    /// which they appear in the file.
+    pub fn file_conflicts(&self, branch: &Branch, key: &Key<PatchId>) -> Result<Vec<Conflict>, Error> {
+        let mut graph = self.retrieve(branch, key);
+        let zombies = graph.lines
+            .iter()
+            .filter(|l| l.is_zombie())
+            .map(|l| l.key.clone())
+            .collect();
+        let mut buf = ConflictBuffer {
+            zombies: zombies,
+            line: 1,
+            current: None,
+            conflicts: Vec::new(),
+        };
+        self.output_file(&mut buf, &mut graph, &mut Vec::new())?;
+        Ok(buf.conflicts)
+    }
+
+    /// The conflicts of all files in a branch.
+    pub fn list_conflicts(&self, branch: &Branch) -> Result<Vec<FileConflicts>, Error> {
+        let mut result = Vec::new();
+        let mut path = PathBuf::new();
+        self.collect_conflicts(branch, &ROOT_KEY, &mut path, &mut result)?;
+        Ok(result)
+    }
+
+    fn collect_conflicts(&self,
+                         branch: &Branch,
+                         key: &Key<PatchId>,
+                         path: &mut PathBuf,
+                         result: &mut Vec<FileConflicts>)
+                         -> Result<(), Error> {
+        for (key, names) in self.list_files_under_node(branch, key) {
+            let (meta, name) = names[0];
+            path.push(name);
+            if meta.is_dir() {
+                self.collect_conflicts(branch, &key, path, result)?
+            } else {
+                let conflicts = self.file_conflicts(branch, &key)?;
+                if !conflicts.is_empty() {
+                    result.push(FileConflicts {
+                        path: path.clone(),
+                        key: key,
+                        conflicts: conflicts,
+                    })
+                }
+            }
+            path.pop();
+        }
+        Ok(())
+    }
+}
+
+impl<A: Transaction, R: rand::Rng> T<A, R> {
+    /// Resolves conflicts in file `path` (relative to the root of the
+    /// repository), by keeping, for each conflict `i` (in the order
+    /// of `file_conflicts`), only side `choices[i]`, or all sides if
+    /// `choices[i]` is `None` or missing.
+    ///
+    /// Returns the changes implementing this resolution, along with
+    /// the new contents of the file.
+    pub fn resolve_conflicts(&self,
+                             branch: &Branch,
+                             path: &Path,
+                             choices: &[Option<usize>],
+                             diff_algorithm: DiffAlgorithm)
+                             -> Result<(Vec<Record>, Vec<u8>), Error> {
+        let inode = self.find_inode(path)?;
+        let key = match self.get_inodes(&inode) {
+            Some(header) if !header.metadata.is_dir() => header.key.clone(),
+            _ => return Err(Error::FileNotInRepo(path.to_path_buf())),
+        };
+        let mut graph = self.retrieve(branch, &key);
+        let mut buf = ResolveBuffer {
+            choices: choices,
+            conflict: None,
+            n_conflicts: 0,
+            contents: Vec::new(),
+        };
+        self.output_file(&mut buf, &mut graph, &mut Vec::new())?;
+        let contents = buf.contents;
+
+        let mut actions = Vec::new();
+        {
+            let lines = read_lines(&contents);
+            let mut graph = self.retrieve(branch, &key);
+            self.diff(branch,
+                      &Rc::new(path.to_path_buf()),
+                      &mut (LineId::new() + 1),
+                      &mut actions,
+                      &mut Vec::new(),
+                      &mut graph,
+                      &lines,
+                      diff_algorithm)?;
+        }
+        Ok((actions, contents))
+    }
+}
@@ -5,6 +5,7 @@ use std::path::PathBuf;
 use bincode;
 use sanakirja;
 use backend;
+use rustc_serialize::base64::{ToBase64, URL_SAFE};
 
 #[derive(Debug)]
 pub enum Error {
@@ -23,7 +24,9 @@ pub enum Error {
     WrongHash,
     BranchNameAlreadyExists,
     ChangesFile,
-    PatchVersionMismatch(u64, u64)
+    PatchVersionMismatch(u64, u64),
+    InvalidKey,
+    MissingDependency(backend::Hash),
 }
 
 impl Error {
@@ -58,6 +61,10 @@ impl fmt::Display for Error {
             Error::BranchNameAlreadyExists => write!(f, "Branch name already exists"),
             Error::ChangesFile => write!(f, "Invalid changes file"),
             Error::PatchVersionMismatch(a, b) => write!(f, "Patch version mismatch: this Pijul knows version {}, the patch is version {}", b, a),
+            Error::InvalidKey => write!(f, "Invalid signing key"),
+            Error::MissingDependency(ref hash) => {
+                write!(f, "Missing dependency {}", hash.to_base64(URL_SAFE))
+            }
         }
     }
 }
@@ -81,6 +88,8 @@ impl std::error::Error for Error {
             Error::BranchNameAlreadyExists => "Branch name already exists",
             Error::ChangesFile => "Invalid changes file",
             Error::PatchVersionMismatch(_, _) => "Patch version mismatch",
+            Error::InvalidKey => "Invalid signing key",
+            Error::MissingDependency(_) => "Missing dependency",
         }
     }
 
@@ -102,6 +111,8 @@ impl std::error::Error for Error {
             Error::BranchNameAlreadyExists => None,
             Error::ChangesFile => None,
             Error::PatchVersionMismatch(_, _) => None,
+            Error::InvalidKey => None,
+            Error::MissingDependency(_) => None,
         }
     }
 }
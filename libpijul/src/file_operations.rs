@@ -8,7 +8,9 @@ use std;
 use std::path::{Path, PathBuf};
 use rustc_serialize::hex::ToHex;
 use std::iter::Iterator;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
+use patch::{Change, Patch};
+use ignore::Ignore;
 
 impl<'env, R: rand::Rng> MutTxn<'env, R> {
     fn mark_inode_moved(&mut self, inode: &Inode) {
@@ -287,6 +289,58 @@ impl<A: Transaction, R> backend::T<A, R> {
 
 
 
+    /// Walks the working copy below `path` (relative to `repo_root`),
+    /// pushing the files that are neither in the tree nor ignored to
+    /// `files`. `inode` is the inode of `path`, if `path` is tracked.
+    fn collect_untracked(&self,
+                         repo_root: &Path,
+                         ignore: &mut Ignore,
+                         inode: Option<&Inode>,
+                         path: &mut PathBuf,
+                         files: &mut Vec<PathBuf>)
+                         -> Result<(), Error> {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(repo_root.join(path.as_path()))? {
+            entries.push(entry?)
+        }
+        entries.sort_by_key(|e| e.file_name());
+        for entry in entries {
+            let name = entry.file_name();
+            path.push(&name);
+            let is_dir = entry.file_type()?.is_dir();
+            if !ignore.is_ignored(path.as_path(), is_dir)? {
+                let child = match (inode, name.to_str()) {
+                    (Some(inode), Some(name)) => {
+                        let file_id = OwnedFileId {
+                            parent_inode: inode.clone(),
+                            basename: SmallString::from_str(name),
+                        };
+                        self.get_tree(&file_id.as_file_id()).cloned()
+                    }
+                    _ => None,
+                };
+                if is_dir {
+                    self.collect_untracked(repo_root, ignore, child.as_ref(), path, files)?
+                } else if child.is_none() {
+                    files.push(path.clone())
+                }
+            }
+            path.pop();
+        }
+        Ok(())
+    }
+
+    /// Returns the files of the working copy rooted at `repo_root`
+    /// that are not in the repository, and not ignored by a
+    /// `.pijulignore` file. The paths are relative to `repo_root`.
+    pub fn untracked_files(&self, repo_root: &Path) -> Result<Vec<PathBuf>, Error> {
+        let mut ignore = Ignore::new(repo_root);
+        let mut files = Vec::new();
+        let mut path = PathBuf::new();
+        self.collect_untracked(repo_root, &mut ignore, Some(&ROOT_INODE), &mut path, &mut files)?;
+        Ok(files)
+    }
+
     pub fn is_directory(&self, inode: &Inode) -> bool {
         let file_id = OwnedFileId {
             parent_inode: inode.clone(),
@@ -368,5 +422,141 @@ impl<A: Transaction, R> backend::T<A, R> {
         result
     }
 
+    /// All the nodes that are or have been part of the file or
+    /// directory `key`, including deleted lines, all the names it has
+    /// had and, for a directory, the names and contents of all files
+    /// under it.
+    pub fn file_history_keys(&self, branch: &Branch, key: &Key<PatchId>) -> HashSet<Key<PatchId>> {
+        let mut keys = HashSet::new();
+        // The name nodes of `key`, including the ones deleted when
+        // the file was moved.
+        for (_, v) in self.iter_nodes(branch, Some((key, None)))
+            .take_while(|&(k, _)| k == key)
+            .filter(|&(_, v)| v.flag.contains(PARENT_EDGE | FOLDER_EDGE)) {
+                keys.insert(v.dest.clone());
+            }
+        let mut stack = vec![key.clone()];
+        while let Some(key) = stack.pop() {
+            if keys.insert(key.clone()) {
+                for (_, v) in self.iter_nodes(branch, Some((&key, None)))
+                    .take_while(|&(k, _)| *k == key)
+                    .filter(|&(_, v)| !v.flag.intersects(PARENT_EDGE | PSEUDO_EDGE)) {
+                        stack.push(v.dest.clone())
+                    }
+            }
+        }
+        keys
+    }
+
+    /// The nodes of `path` (relative to the root of the repository)
+    /// and of its history, as in `file_history_keys`, identified by
+    /// the hash of the patch that introduced them. The parts of
+    /// `path` that are not in `branch` (or all parts, if `branch` is
+    /// `None`) are looked up in the files added by `patches`, which
+    /// need not be applied.
+    pub fn path_history_keys(&self,
+                             branch: Option<&Branch>,
+                             path: &Path,
+                             patches: &[(Hash, Patch)])
+                             -> HashSet<Key<Hash>> {
+        let mut current = Key {
+            patch: Hash::None,
+            line: ROOT_KEY.line.clone(),
+        };
+        for component in path.components() {
+            let name = component.as_os_str().to_string_lossy();
+            let local = branch.and_then(|branch| {
+                self.internal_key_opt(&Key {
+                        patch: Some(current.patch.clone()),
+                        line: current.line.clone(),
+                    })
+                    .map(|key| (branch, key))
+                })
+                .and_then(|(branch, key)| {
+                    self.list_files_under_node(branch, &key)
+                        .into_iter()
+                        .find(|&(_, ref names)| names.iter().any(|&(_, n)| n == name))
+                        .and_then(|(key, _)| self.external_key(&key))
+                })
+                .and_then(hash_key);
+            let found = local.or_else(|| find_added_file(patches, &current, &name));
+            current = match found {
+                Some(key) => key,
+                None => return HashSet::new(),
+            }
+        }
+        let local = branch.and_then(|branch| {
+            self.internal_key_opt(&Key {
+                    patch: Some(current.patch.clone()),
+                    line: current.line.clone(),
+                })
+                .map(|key| (branch, key))
+        });
+        match local {
+            Some((branch, key)) => {
+                self.file_history_keys(branch, &key)
+                    .iter()
+                    .filter_map(|key| self.external_key(key))
+                    .filter_map(hash_key)
+                    .collect()
+            }
+            None => {
+                let mut keys = HashSet::new();
+                keys.insert(current);
+                keys
+            }
+        }
+    }
+
+    /// Tells whether `patch`, whose internal id is `internal`, adds,
+    /// deletes or uses as a context one of the nodes in `keys`, as
+    /// returned by `file_history_keys`.
+    pub fn patch_touches(&self, keys: &HashSet<Key<PatchId>>, internal: &PatchId, patch: &Patch) -> bool {
+        if keys.iter().any(|k| k.patch == *internal) {
+            return true
+        }
+        patch.changes.iter().any(|change| {
+            match *change {
+                Change::NewNodes { ref up_context, ref down_context, .. } => {
+                    up_context.iter()
+                        .chain(down_context.iter())
+                        .any(|k| keys.contains(&self.internal_key(k, internal)))
+                }
+                Change::NewEdges { ref edges, .. } => {
+                    edges.iter().any(|e| {
+                        keys.contains(&self.internal_key(&e.from, internal)) ||
+                            keys.contains(&self.internal_key(&e.to, internal))
+                    })
+                }
+            }
+        })
+    }
+}
+
+/// `key`, if it is not a key introduced by the patch it appears in.
+fn hash_key(key: Key<Option<Hash>>) -> Option<Key<Hash>> {
+    let line = key.line.clone();
+    key.patch.map(|patch| Key { patch: patch, line: line })
+}
 
+/// The node of the file called `name` in directory `parent`, added by
+/// one of `patches`.
+fn find_added_file(patches: &[(Hash, Patch)], parent: &Key<Hash>, name: &str) -> Option<Key<Hash>> {
+    for &(ref hash, ref patch) in patches {
+        for change in patch.changes.iter() {
+            if let Change::NewNodes { ref up_context, ref line_num, ref flag, ref nodes, .. } = *change {
+                let is_parent = |k: &Key<Option<Hash>>| {
+                    k.patch.as_ref().unwrap_or(hash) == &parent.patch && k.line == parent.line
+                };
+                if flag.contains(FOLDER_EDGE) && nodes.len() >= 2 && nodes[0].len() >= 2 &&
+                    &nodes[0][2..] == name.as_bytes() && up_context.iter().any(is_parent) {
+                    return Some(Key {
+                        patch: hash.clone(),
+                        line: *line_num + 1,
+                    })
+                }
+            }
+        }
+    }
+    None
 }
@@ -31,6 +31,14 @@ pub fn patches_dir<P: AsRef<Path>>(p: P) -> PathBuf {
     return p.as_ref().join(PIJUL_DIR_NAME).join(PATCHES_DIR_NAME);
 }
 
+pub const STASH_DIR_NAME: &'static str = "stash";
+
+/// Directory where `pijul stash` saves unrecorded changes, as
+/// patches applied to no branch.
+pub fn stash_dir<P: AsRef<Path>>(p: P) -> PathBuf {
+    return p.as_ref().join(PIJUL_DIR_NAME).join(STASH_DIR_NAME);
+}
+
 pub fn branch_changes_base_path(b: &str) -> String {
     "changes.".to_string() + &b.as_bytes().to_base64(URL_SAFE)
 }
@@ -39,6 +47,20 @@ pub fn branch_changes_file(p: &Path, b: &str) -> PathBuf {
     p.join(PIJUL_DIR_NAME).join(branch_changes_base_path(b))
 }
 
+pub const REMOTES_DIR_NAME: &'static str = "remotes";
+
+/// Directory where pull and push record the patches they have seen
+/// on remote repositories.
+pub fn remotes_dir<P: AsRef<Path>>(p: P) -> PathBuf {
+    return p.as_ref().join(PIJUL_DIR_NAME).join(REMOTES_DIR_NAME);
+}
+
+/// File listing the patches known to be on branch `b` of remote
+/// `remote`, in the format of the changes files.
+pub fn remote_changes_file(p: &Path, remote: &str, b: &str) -> PathBuf {
+    remotes_dir(p).join(remote.as_bytes().to_base64(URL_SAFE)).join(branch_changes_base_path(b))
+}
+
 pub fn id_file(p: &Path) -> PathBuf {
     p.join(PIJUL_DIR_NAME).join("id")
 }
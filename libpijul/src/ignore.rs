@@ -0,0 +1,295 @@
+//! Ignore files, listing paths that must never be tracked.
+//!
+//! Each directory of the working copy may contain a file called
+//! `.pijulignore`, with one pattern per line. The syntax is the same
+//! as gitignore's:
+//!
+//! - blank lines and lines starting with `#` are skipped;
+//! - `*` matches anything but `/`, `?` matches any single character
+//!   but `/`, `[a-z]` matches a character class, and `**` matches any
+//!   number of directories;
+//! - a pattern starting with `!` re-includes paths excluded by a
+//!   previous pattern;
+//! - a pattern ending with `/` only matches directories;
+//! - a pattern containing a `/` (other than a trailing one) is
+//!   relative to the directory of the ignore file, else it is matched
+//!   against file names at any depth below that directory.
+//!
+//! Patterns from deeper ignore files take precedence over patterns
+//! from their ancestors, and later patterns take precedence over
+//! earlier ones. As in git, a path inside an ignored directory cannot
+//! be re-included, and patterns only apply to untracked files: a file
+//! already added is recorded even if it matches a pattern.
+
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::io;
+use fs_representation::PIJUL_DIR_NAME;
+use error::Error;
+
+pub const IGNORE_FILE_NAME: &'static str = ".pijulignore";
+
+/// A pattern of an ignore file, also used in attributes files.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    glob: Vec<u8>,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl Pattern {
+    /// Parses a line of an ignore file, returning `None` for blank
+    /// lines and comments.
+    pub fn parse(line: &str) -> Option<Pattern> {
+        let line = line.trim_right_matches(|c| c == '\r' || c == '\n');
+        // Trailing spaces are ignored unless escaped.
+        let mut line = line.as_bytes();
+        while line.len() > 0 && line[line.len() - 1] == b' '
+            && !(line.len() >= 2 && line[line.len() - 2] == b'\\') {
+                line = &line[..line.len() - 1]
+            }
+        if line.is_empty() || line[0] == b'#' {
+            return None
+        }
+        let (negated, line) = if line[0] == b'!' {
+            (true, &line[1..])
+        } else if line.len() >= 2 && (&line[..2] == b"\\!" || &line[..2] == b"\\#") {
+            (false, &line[1..])
+        } else {
+            (false, line)
+        };
+        let (dir_only, line) = if line.len() > 0 && line[line.len() - 1] == b'/' {
+            (true, &line[..line.len() - 1])
+        } else {
+            (false, line)
+        };
+        let anchored = line.contains(&b'/');
+        let line = if line.len() > 0 && line[0] == b'/' {
+            &line[1..]
+        } else {
+            line
+        };
+        if line.is_empty() {
+            return None
+        }
+        Some(Pattern {
+            glob: line.to_vec(),
+            negated: negated,
+            dir_only: dir_only,
+            anchored: anchored,
+        })
+    }
+
+    /// Whether this pattern starts with `!`.
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    /// `relative` is the path relative to the directory of the ignore
+    /// file this pattern comes from, with `/` as a separator.
+    pub fn matches(&self, relative: &[u8], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false
+        }
+        if self.anchored {
+            glob_match(&self.glob, relative)
+        } else {
+            let basename = match relative.iter().rposition(|&c| c == b'/') {
+                Some(i) => &relative[i + 1..],
+                None => relative,
+            };
+            glob_match(&self.glob, basename)
+        }
+    }
+}
+
+/// Matches `text` against a single glob, where `*` and `?` never
+/// match `/`, but `**` does.
+fn glob_match(glob: &[u8], text: &[u8]) -> bool {
+    if glob.is_empty() {
+        return text.is_empty()
+    }
+    match glob[0] {
+        b'*' if glob.len() >= 2 && glob[1] == b'*' => {
+            let rest = &glob[2..];
+            if rest.len() > 0 && rest[0] == b'/' {
+                // "**/" matches zero or more directories.
+                if glob_match(&rest[1..], text) {
+                    return true
+                }
+            }
+            for i in 0..(text.len() + 1) {
+                if glob_match(rest, &text[i..]) {
+                    return true
+                }
+            }
+            false
+        }
+        b'*' => {
+            let rest = &glob[1..];
+            for i in 0..(text.len() + 1) {
+                if glob_match(rest, &text[i..]) {
+                    return true
+                }
+                if i < text.len() && text[i] == b'/' {
+                    break
+                }
+            }
+            false
+        }
+        b'?' => {
+            text.len() > 0 && text[0] != b'/' && glob_match(&glob[1..], &text[1..])
+        }
+        b'[' => {
+            if text.is_empty() || text[0] == b'/' {
+                return false
+            }
+            match match_class(&glob[1..], text[0]) {
+                Some((true, len)) => glob_match(&glob[1 + len..], &text[1..]),
+                Some((false, _)) => false,
+                // Unterminated class: match '[' literally.
+                None => text[0] == b'[' && glob_match(&glob[1..], &text[1..]),
+            }
+        }
+        b'\\' if glob.len() >= 2 => {
+            text.len() > 0 && text[0] == glob[1] && glob_match(&glob[2..], &text[1..])
+        }
+        c => {
+            text.len() > 0 && text[0] == c && glob_match(&glob[1..], &text[1..])
+        }
+    }
+}
+
+/// Matches a character class (after the opening `[`), returning
+/// whether `c` matches, and the length of the class including its
+/// closing `]`.
+fn match_class(class: &[u8], c: u8) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let negated = if class.len() > 0 && (class[0] == b'!' || class[0] == b'^') {
+        i += 1;
+        true
+    } else {
+        false
+    };
+    let mut matched = false;
+    let mut first = true;
+    while i < class.len() {
+        if class[i] == b']' && !first {
+            return Some((matched != negated, i + 1))
+        }
+        first = false;
+        let lo = if class[i] == b'\\' && i + 1 < class.len() {
+            i += 1;
+            class[i]
+        } else {
+            class[i]
+        };
+        if i + 2 < class.len() && class[i + 1] == b'-' && class[i + 2] != b']' {
+            let hi = class[i + 2];
+            if lo <= c && c <= hi {
+                matched = true
+            }
+            i += 3
+        } else {
+            if lo == c {
+                matched = true
+            }
+            i += 1
+        }
+    }
+    None
+}
+
+/// The ignore rules of a working copy. Ignore files are read lazily,
+/// the first time a path under their directory is tested.
+#[derive(Debug)]
+pub struct Ignore {
+    repo_root: PathBuf,
+    files: HashMap<PathBuf, Vec<Pattern>>,
+}
+
+impl Ignore {
+    pub fn new<P: AsRef<Path>>(repo_root: P) -> Self {
+        Ignore {
+            repo_root: repo_root.as_ref().to_path_buf(),
+            files: HashMap::new(),
+        }
+    }
+
+    pub fn repo_root(&self) -> &Path {
+        &self.repo_root
+    }
+
+    /// Load the ignore file of directory `dir` (relative to the
+    /// repository root), if not already loaded.
+    fn load(&mut self, dir: &Path) -> Result<(), Error> {
+        if self.files.contains_key(dir) {
+            return Ok(())
+        }
+        let mut patterns = Vec::new();
+        let path = self.repo_root.join(dir).join(IGNORE_FILE_NAME);
+        match File::open(&path) {
+            Ok(f) => {
+                for line in BufReader::new(f).lines() {
+                    if let Some(p) = Pattern::parse(&line?) {
+                        patterns.push(p)
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::IO(e)),
+        }
+        debug!("ignore patterns for {:?}: {:?}", dir, patterns);
+        self.files.insert(dir.to_path_buf(), patterns);
+        Ok(())
+    }
+
+    /// Tells whether the path `path` (relative to the repository
+    /// root) is ignored, not taking its ancestors into account.
+    fn is_ignored_here(&mut self, path: &Path, is_dir: bool) -> Result<bool, Error> {
+        let mut ignored = false;
+        let mut dir = PathBuf::new();
+        let mut ancestors = vec![dir.clone()];
+        if let Some(parent) = path.parent() {
+            for c in parent.components() {
+                dir.push(c.as_os_str());
+                ancestors.push(dir.clone())
+            }
+        }
+        for dir in ancestors.iter() {
+            self.load(dir)?;
+            let relative = path.strip_prefix(dir).unwrap();
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            for p in self.files.get(dir).unwrap().iter() {
+                if p.negated == ignored && p.matches(relative.as_bytes(), is_dir) {
+                    ignored = !p.negated
+                }
+            }
+        }
+        Ok(ignored)
+    }
+
+    /// Tells whether the path `path`, relative to the repository root,
+    /// is ignored. This is the case if the path is the repository
+    /// directory, or if it or one of its ancestors is matched by an
+    /// ignore pattern.
+    pub fn is_ignored<P: AsRef<Path>>(&mut self, path: P, is_dir: bool) -> Result<bool, Error> {
+        let path = path.as_ref();
+        let mut prefix = PathBuf::new();
+        let mut components = path.components().peekable();
+        while let Some(c) = components.next() {
+            prefix.push(c.as_os_str());
+            if prefix.as_os_str() == PIJUL_DIR_NAME {
+                return Ok(true)
+            }
+            let prefix_is_dir = components.peek().is_some() || is_dir;
+            if self.is_ignored_here(&prefix, prefix_is_dir)? {
+                return Ok(true)
+            }
+        }
+        Ok(false)
+    }
+}
@@ -12,6 +12,7 @@ extern crate ring;
 extern crate rustc_serialize;
 extern crate sanakirja;
 extern crate serde;
+extern crate untrusted;
 #[macro_use]
 extern crate serde_derive;
 
@@ -35,15 +36,21 @@ pub trait RepositoryEnv<'env, R>: Sized {
 mod backend;
 
 mod apply;
+mod chunks;
+mod myers;
 mod optimal_diff;
 mod output;
+mod patience;
 mod record;
 mod unrecord;
+pub mod attributes;
 pub mod conflict;
 pub mod file_operations;
 pub mod fs_representation;
 pub mod graph;
+pub mod ignore;
 pub mod patch;
+pub mod signature;
 
 pub use backend::{
     DEFAULT_BRANCH, Repository, MutTxn, LineId, PatchId, FOLDER_EDGE, PARENT_EDGE, DELETED_EDGE,
@@ -55,8 +62,9 @@ pub use backend::{
     ApplyTimestamp
 };
 
-pub use record::InodeUpdate;
+pub use record::{InodeUpdate, PathStatus, StatusKind};
 pub use patch::Patch;
+pub use optimal_diff::DiffAlgorithm;
 pub use sanakirja::value::Value;
 use fs_representation::ID_LENGTH;
 use std::io::Read;
@@ -161,14 +169,16 @@ fn make_remote<'a, I:Iterator<Item = &'a Hash>>(target: &Path, remote: I) -> Res
 /// function again.
 ///
 /// Also, this function takes a file lock on the repository.
-pub fn apply_resize<'a, I:Iterator<Item = &'a Hash>>(target: &Path, branch_name: &str, remote: I) -> Result<(), Error> {
+pub fn apply_resize<'a, I:Iterator<Item = &'a Hash>>(target: &Path, branch_name: &str, remote: I,
+                                                     defaults: &attributes::FileAttributes)
+                                                     -> Result<(), Error> {
     use fs_representation::*;
     let (patches, size_increase) = make_remote(target, remote)?;
     info!("applying patches with size_increase {:?}", size_increase);
     let pristine_dir = pristine_dir(target).to_path_buf();;
     let repo = try!(Repository::open(pristine_dir, Some(size_increase as u64)));
     let mut txn = try!(repo.mut_txn_begin(rand::thread_rng()));
-    try!(txn.apply_patches(branch_name, target, &patches));
+    try!(txn.apply_patches(branch_name, target, &patches, defaults));
     try!(txn.commit());
     Ok(())
 }
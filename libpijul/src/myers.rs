@@ -0,0 +1,132 @@
+//! Longest common subsequence of two sequences in linear memory, as
+//! in Myers, "An O(ND) difference algorithm and its variations"
+//! (1986), section 4b: the middle of an optimal path is found by
+//! running the algorithm forward from the beginning and backward from
+//! the end of the sequences at the same time, and the two halves are
+//! then solved recursively.
+
+/// Sets `matches[i]` to `Some(j)` if `a[i]` is matched with `b[j]` in
+/// a longest common subsequence of `a` and `b`, leaving the other
+/// elements of `matches` untouched.
+pub fn lcs<T: Eq>(a: &[T], b: &[T], matches: &mut [Option<usize>]) {
+    lcs_range(a, 0, a.len(), b, 0, b.len(), matches)
+}
+
+/// Same as `lcs`, on `a[a0..a1]` and `b[b0..b1]`.
+pub fn lcs_range<T: Eq>(a: &[T],
+                        mut a0: usize,
+                        mut a1: usize,
+                        b: &[T],
+                        mut b0: usize,
+                        mut b1: usize,
+                        matches: &mut [Option<usize>]) {
+    while a0 < a1 && b0 < b1 && a[a0] == b[b0] {
+        matches[a0] = Some(b0);
+        a0 += 1;
+        b0 += 1;
+    }
+    while a0 < a1 && b0 < b1 && a[a1 - 1] == b[b1 - 1] {
+        matches[a1 - 1] = Some(b1 - 1);
+        a1 -= 1;
+        b1 -= 1;
+    }
+    if a0 == a1 || b0 == b1 {
+        return
+    }
+    if let Some((x, y)) = middle(a, a0, a1, b, b0, b1) {
+        lcs_range(a, a0, a0 + x, b, b0, b0 + y, matches);
+        lcs_range(a, a0 + x, a1, b, b0 + y, b1, matches);
+    }
+}
+
+/// Finds a point `(x, y)` (relative to `(a0, b0)`) in the middle of
+/// an optimal path from `(a0, b0)` to `(a1, b1)`, or `None` if the
+/// two ranges have nothing in common. The ranges must not start or
+/// end with a common element.
+fn middle<T: Eq>(a: &[T],
+                 a0: usize,
+                 a1: usize,
+                 b: &[T],
+                 b0: usize,
+                 b1: usize)
+                 -> Option<(usize, usize)> {
+    let n = (a1 - a0) as isize;
+    let m = (b1 - b0) as isize;
+    let max_d = (n + m + 1) / 2;
+    let offset = max_d;
+    let len = 2 * max_d + 2;
+    // Furthest x reached on each diagonal k = x - y, going forward
+    // from the beginning, and backward from the end.
+    let mut vf = vec![-1isize; len as usize];
+    let mut vb = vec![-1isize; len as usize];
+    vf[(offset + 1) as usize] = 0;
+    vb[(offset + 1) as usize] = 0;
+    let delta = n - m;
+    // If delta is odd, the paths meet on a forward step, else on a
+    // backward step.
+    let front = delta % 2 != 0;
+    // Diagonals outside of the grid are skipped.
+    let (mut kf_start, mut kf_end, mut kb_start, mut kb_end) = (0, 0, 0, 0);
+    let mut d = 0;
+    while d < max_d {
+        let mut k = -d + kf_start;
+        while k <= d - kf_end {
+            let kf = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && vf[kf - 1] < vf[kf + 1]) {
+                vf[kf + 1]
+            } else {
+                vf[kf - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[a0 + x as usize] == b[b0 + y as usize] {
+                x += 1;
+                y += 1;
+            }
+            vf[kf] = x;
+            if x > n {
+                kf_end += 2
+            } else if y > m {
+                kf_start += 2
+            } else if front {
+                let kb = offset + delta - k;
+                if kb >= 0 && kb < len && vb[kb as usize] != -1 && x >= n - vb[kb as usize] {
+                    return Some((x as usize, y as usize))
+                }
+            }
+            k += 2
+        }
+        let mut k = -d + kb_start;
+        while k <= d - kb_end {
+            let kb = (offset + k) as usize;
+            let mut x = if k == -d || (k != d && vb[kb - 1] < vb[kb + 1]) {
+                vb[kb + 1]
+            } else {
+                vb[kb - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m &&
+                  a[a0 + (n - x - 1) as usize] == b[b0 + (m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            vb[kb] = x;
+            if x > n {
+                kb_end += 2
+            } else if y > m {
+                kb_start += 2
+            } else if !front {
+                let kf = offset + delta - k;
+                if kf >= 0 && kf < len && vf[kf as usize] != -1 {
+                    let xf = vf[kf as usize];
+                    let yf = offset + xf - kf;
+                    if xf >= n - x {
+                        return Some((xf as usize, yf as usize))
+                    }
+                }
+            }
+            k += 2
+        }
+        d += 1
+    }
+    None
+}
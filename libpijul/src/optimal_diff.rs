@@ -12,6 +12,8 @@ use std::path::PathBuf;
 use std::rc::Rc;
 use std::cmp::min;
 use conflict;
+use myers;
+use patience;
 use std::collections::HashMap;
 
 struct Diff<'a, T: 'a> {
@@ -103,6 +105,183 @@ impl<T> IndexMut<usize> for Matrix<T> {
     }
 }
 
+/// Above this number of cells in the matrix of `Lcs::Matrix`, the
+/// longest common subsequence is computed in linear memory instead.
+const MAX_MATRIX_SIZE: usize = 1 << 22;
+
+/// How the lines of the two versions of a file are aligned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiffAlgorithm {
+    /// A longest common subsequence of the lines, which gives the
+    /// smallest patches.
+    #[serde(rename = "myers")]
+    Myers,
+    /// Lines that appear exactly once in both versions are aligned
+    /// first. Patches may be slightly larger, but follow the
+    /// structure of the file more closely when moving blocks around.
+    #[serde(rename = "patience")]
+    Patience,
+}
+
+impl Default for DiffAlgorithm {
+    fn default() -> Self {
+        DiffAlgorithm::Myers
+    }
+}
+
+/// An alignment of the two versions of a file, along which
+/// `local_diff` walks.
+enum Lcs {
+    /// The lengths of the longest common subsequences of all the
+    /// suffixes of both versions, in quadratic memory.
+    Matrix(Matrix<usize>),
+    /// For each line of the old version, the line of the new version
+    /// it is matched with, computed in linear memory.
+    Matches(Vec<Option<usize>>),
+}
+
+impl Lcs {
+    /// Whether line `i` of the old version is matched with line `j`
+    /// of the new version, `eq` being true if these lines are equal.
+    fn is_match(&self, i: usize, j: usize, eq: bool) -> bool {
+        match *self {
+            Lcs::Matrix(_) => eq,
+            Lcs::Matches(ref m) => m[i] == Some(j),
+        }
+    }
+
+    /// Whether, when lines `i` and `j` are not matched, line `i`
+    /// should be deleted before adding line `j`.
+    fn delete_first(&self, i: usize, j: usize) -> bool {
+        match *self {
+            Lcs::Matrix(ref opt) => opt[i + 1][j] >= opt[i][j + 1],
+            Lcs::Matches(ref m) => m[i].is_none(),
+        }
+    }
+}
+
+/// Numbers of lines at the beginning and at the end of the two
+/// versions of a file that are equal. The first line of the old
+/// version is the up context of the file, and is not compared.
+fn common_ends<'a, A: Transaction + 'a>(diff: &Diff<A>, b: &[&'a [u8]]) -> (usize, usize) {
+    let leading_equals = diff.contents_a.iter()
+        .skip(1)
+        .zip(b.iter())
+        .take_while(|&(a, b)| {
+            let b: Value<'a, A> = Value::from_slice(b);
+            a.clone().eq(b)
+        })
+        .count();
+
+    let trailing_equals = if leading_equals >=
+        std::cmp::min(diff.contents_a.len() - 1, b.len()) {
+            0
+        } else {
+            (&diff.contents_a[leading_equals+1..]).iter().rev()
+                .zip((&b[leading_equals+1..]).iter().rev())
+                .take_while(|&(a, b)| {
+                    let b: Value<'a, A> = Value::from_slice(b);
+                    a.clone().eq(b)
+                })
+                .count()
+        };
+
+
+    // Now, if there are repeated lines in the middle,
+    // (leading_equals + trailing_equals) might be larger than the
+    // size of one of the files. Check that:
+    let trailing_equals =
+        min(trailing_equals,
+            min(diff.contents_a.len() - leading_equals,
+                b.len() - leading_equals));
+    debug!("equals: {:?} {:?}", leading_equals, trailing_equals);
+    (leading_equals, trailing_equals)
+}
+
+/// Computes the lengths of the longest common subsequences of
+/// all the suffixes of the two versions, between the common
+/// leading and trailing lines.
+fn matrix_lcs<'a, A: Transaction + 'a>(diff: &Diff<A>,
+                                       b: &[&'a [u8]],
+                                       leading_equals: usize,
+                                       trailing_equals: usize)
+                                       -> Lcs {
+    let mut opt = Matrix::new(diff.contents_a.len() + 1 - leading_equals - trailing_equals,
+                              b.len() + 1 - leading_equals - trailing_equals,
+                              0);
+    debug!("opt.rows: {:?}, opt.cols: {:?}", opt.rows, opt.cols);
+    if diff.contents_a.len() - trailing_equals - leading_equals > 0 {
+        let mut i = diff.contents_a.len() - 1 - trailing_equals - leading_equals;
+        loop {
+            if b.len() - trailing_equals - leading_equals > 0 {
+                let mut j = b.len() - 1 - trailing_equals - leading_equals;
+                loop {
+                    let contents_a_i = diff.contents_a[leading_equals + i].clone();
+                    let contents_b_j: Value<'a, A> = Value::from_slice(&b[leading_equals + j]);
+                    opt[i][j] = if contents_a_i.eq(contents_b_j) {
+                        opt[i + 1][j + 1] + 1
+                    } else {
+                        std::cmp::max(opt[i + 1][j], opt[i][j + 1])
+                    };
+                    if j > 0 {
+                        j -= 1
+                    } else {
+                        break;
+                    }
+                }
+            }
+            if i > 0 {
+                i -= 1
+            } else {
+                break;
+            }
+        }
+    }
+    Lcs::Matrix(opt)
+}
+
+/// Matches the lines of the two versions, between the common
+/// leading and trailing lines, with `align`, in memory linear in
+/// the size of the files.
+fn linear_lcs<A: Transaction>(diff: &Diff<A>,
+                              b: &[&[u8]],
+                              leading_equals: usize,
+                              trailing_equals: usize,
+                              align: fn(&[usize], &[usize], &mut [Option<usize>]))
+                              -> Lcs {
+    // `local_diff` compares line `leading_equals + i` of the old
+    // version, for i >= 1, with line `leading_equals + j` of the
+    // new version, for j >= 0.
+    let end_a = diff.contents_a.len() - trailing_equals;
+    let start_a = std::cmp::min(leading_equals + 1, end_a);
+    let end_b = b.len() - trailing_equals;
+    let contents_a: Vec<Vec<u8>> = diff.contents_a[start_a..end_a]
+        .iter()
+        .map(|line| {
+            let mut v = Vec::new();
+            for chunk in line.clone() {
+                v.extend(chunk)
+            }
+            v
+        })
+        .collect();
+
+    // Compare numbers instead of lines.
+    let mut ids = HashMap::new();
+    let mut id = |line: &[u8]| -> usize {
+        let n = ids.len();
+        *ids.entry(line.to_vec()).or_insert(n)
+    };
+    let ids_a: Vec<usize> = contents_a.iter().map(|l| id(&l[..])).collect();
+    let ids_b: Vec<usize> = b[leading_equals..end_b].iter().map(|l| id(*l)).collect();
+
+    let mut matches_a = vec![None; ids_a.len()];
+    align(&ids_a, &ids_b, &mut matches_a);
+    let mut matches = vec![None];
+    matches.extend(matches_a.into_iter());
+    Lcs::Matches(matches)
+}
+
 impl<A: Transaction, R: rand::Rng> T<A, R> {
     fn delete_edges(&self,
                     branch: &Branch,
@@ -282,75 +461,24 @@ impl<A: Transaction, R: rand::Rng> T<A, R> {
                       actions: &mut Vec<Record>,
                       line_num: &mut LineId,
                       diff: &Diff<A>,
-                      b: &[&'a [u8]]) {
+                      b: &[&'a [u8]],
+                      algorithm: DiffAlgorithm) {
         debug!("local_diff {} {}", diff.contents_a.len(), b.len());
 
-        // Compute the costs.
-
-        // Start by computing the leading and trailing equalities.
-        let leading_equals = diff.contents_a.iter()
-            .skip(1)
-            .zip(b.iter())
-            .take_while(|&(a, b)| {
-                let b: Value<'a, A> = Value::from_slice(b);
-                a.clone().eq(b)
-            })
-            .count();
+        let (leading_equals, trailing_equals) = common_ends(diff, b);
 
-        let trailing_equals = if leading_equals >=
-            std::cmp::min(diff.contents_a.len() - 1, b.len()) {
-                0
-            } else {
-                (&diff.contents_a[leading_equals+1..]).iter().rev()
-                    .zip((&b[leading_equals+1..]).iter().rev())
-                    .take_while(|&(a, b)| {
-                        let b: Value<'a, A> = Value::from_slice(b);
-                        a.clone().eq(b)
-                    })
-                    .count()
-            };
-
-
-        // Now, if there are repeated lines in the middle,
-        // (leading_equals + trailing_equals) might be larger than the
-        // size of one of the files. Check that:
-        let trailing_equals =
-            min(trailing_equals,
-                min(diff.contents_a.len() - leading_equals,
-                    b.len() - leading_equals));
-        debug!("equals: {:?} {:?}", leading_equals, trailing_equals);
-
-        let mut opt = Matrix::new(diff.contents_a.len() + 1 - leading_equals - trailing_equals,
-                                  b.len() + 1 - leading_equals - trailing_equals,
-                                  0);
-        debug!("opt.rows: {:?}, opt.cols: {:?}", opt.rows, opt.cols);
-        if diff.contents_a.len() - trailing_equals - leading_equals > 0 {
-            let mut i = diff.contents_a.len() - 1 - trailing_equals - leading_equals;
-            loop {
-                if b.len() - trailing_equals - leading_equals > 0 {
-                    let mut j = b.len() - 1 - trailing_equals - leading_equals;
-                    loop {
-                        let contents_a_i = diff.contents_a[leading_equals + i].clone();
-                        let contents_b_j: Value<'a, A> = Value::from_slice(&b[leading_equals + j]);
-                        opt[i][j] = if contents_a_i.eq(contents_b_j) {
-                            opt[i + 1][j + 1] + 1
-                        } else {
-                            std::cmp::max(opt[i + 1][j], opt[i][j + 1])
-                        };
-                        if j > 0 {
-                            j -= 1
-                        } else {
-                            break;
-                        }
-                    }
-                }
-                if i > 0 {
-                    i -= 1
-                } else {
-                    break;
-                }
+        let rows = diff.contents_a.len() + 1 - leading_equals - trailing_equals;
+        let cols = b.len() + 1 - leading_equals - trailing_equals;
+        debug!("rows: {:?}, cols: {:?}", rows, cols);
+        let lcs = match algorithm {
+            DiffAlgorithm::Patience => {
+                linear_lcs(diff, b, leading_equals, trailing_equals, patience::lcs)
             }
-        }
+            DiffAlgorithm::Myers if rows.saturating_mul(cols) > MAX_MATRIX_SIZE => {
+                linear_lcs(diff, b, leading_equals, trailing_equals, myers::lcs)
+            }
+            DiffAlgorithm::Myers => matrix_lcs(diff, b, leading_equals, trailing_equals),
+        };
 
         // Create the patches.
         let mut i = 1;
@@ -364,12 +492,12 @@ impl<A: Transaction, R: rand::Rng> T<A, R> {
         // self-conflicting if we don't do this).
         let mut pending_change = Pending::None;
 
-        while i < opt.rows - 1 && j < opt.cols - 1 {
+        while i < rows - 1 && j < cols - 1 {
             debug!("i={}, j={}", i, j);
             let contents_a_i = diff.contents_a[leading_equals + i].clone();
             let contents_b_j: Value<'a, A> = Value::from_slice(b[leading_equals + j]);
 
-            if contents_a_i.eq(contents_b_j) {
+            if lcs.is_match(i, j, contents_a_i.eq(contents_b_j)) {
                 // Two lines are equal. If we were collecting lines to
                 // add or delete, we must stop here (in order to get
                 // the smallest possible patch).
@@ -457,7 +585,7 @@ impl<A: Transaction, R: rand::Rng> T<A, R> {
             } else {
                 // Else, the current lines on each side are not equal:
                 debug!("not eq");
-                if opt[i + 1][j] >= opt[i][j + 1] {
+                if lcs.delete_first(i, j) {
                     // We will delete things starting from i (included).
                     // If we are currently adding stuff, finish that.
                     if let Some(j0) = oj.take() {
@@ -509,7 +637,7 @@ impl<A: Transaction, R: rand::Rng> T<A, R> {
         }
         // Alright, we're at the end of either the original file, or the new version.
         debug!("i = {:?}, j = {:?}, line_a {:?}, b {:?}", i, j, diff.lines_a, b);
-        if i < opt.rows - 1 {
+        if i < rows - 1 {
             // There are remaining deletions, i.e. things from the
             // original file are not in the new version.
             let i = leading_equals + i;
@@ -553,7 +681,7 @@ impl<A: Transaction, R: rand::Rng> T<A, R> {
                 })
             }
 
-        } else if j < opt.cols - 1 {
+        } else if j < cols - 1 {
             // There's a pending block to add at the end of the file.
             let j = leading_equals + j;
             let mut i = leading_equals + i;
@@ -616,7 +744,8 @@ impl<A: Transaction, R: rand::Rng> T<A, R> {
                     actions: &mut Vec<Record>,
                     redundant: &mut Vec<(Key<PatchId>, Edge)>,
                     a: &mut Graph,
-                    lines_b: &[&[u8]])
+                    lines_b: &[&[u8]],
+                    algorithm: DiffAlgorithm)
                     -> Result<(), Error> {
 
         let mut d = Diff {
@@ -632,7 +761,8 @@ impl<A: Transaction, R: rand::Rng> T<A, R> {
                         actions,
                         line_num,
                         &d,
-                        &lines_b);
+                        &lines_b,
+                        algorithm);
         Ok(())
     }
 }
@@ -655,3 +785,207 @@ pub fn read_lines(buf_b: &[u8]) -> Vec<&[u8]> {
     }
     lines_b
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Diff, common_ends, matrix_lcs, linear_lcs};
+    use myers;
+    use patience;
+    use sanakirja;
+    use sanakirja::value::Value;
+    use std::collections::HashMap;
+
+    type Txn = sanakirja::Txn<'static>;
+
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    enum Step {
+        Keep,
+        Delete,
+        Add,
+    }
+
+    /// The old version of a file, as output by `output_file`, with
+    /// its up context first.
+    fn old_version<'a>(lines: &[&'a [u8]]) -> Diff<'a, Txn> {
+        let mut contents_a = vec![Value::from_slice(b"")];
+        contents_a.extend(lines.iter().map(|l| Value::from_slice(l)));
+        Diff {
+            lines_a: Vec::new(),
+            contents_a: contents_a,
+            conflicts_ancestors: HashMap::new(),
+            conflicts_descendants: HashMap::new(),
+            current_conflict_ancestor: None,
+        }
+    }
+
+    /// The sequence of lines kept, deleted and added by `local_diff`
+    /// along `lcs`, which determines the records it outputs.
+    fn steps(a: &[&[u8]],
+             b: &[&[u8]],
+             lcs: Option<fn(&[usize], &[usize], &mut [Option<usize>])>)
+             -> Vec<Step> {
+        let diff = old_version(a);
+        let (leading_equals, trailing_equals) = common_ends(&diff, b);
+        let lcs = match lcs {
+            Some(align) => linear_lcs(&diff, b, leading_equals, trailing_equals, align),
+            None => matrix_lcs(&diff, b, leading_equals, trailing_equals),
+        };
+        let rows = diff.contents_a.len() + 1 - leading_equals - trailing_equals;
+        let cols = b.len() + 1 - leading_equals - trailing_equals;
+        let mut steps = vec![Step::Keep; leading_equals];
+        let (mut i, mut j) = (1, 0);
+        while i < rows - 1 && j < cols - 1 {
+            let b_j: Value<Txn> = Value::from_slice(b[leading_equals + j]);
+            if lcs.is_match(i, j, diff.contents_a[leading_equals + i].clone().eq(b_j)) {
+                steps.push(Step::Keep);
+                i += 1;
+                j += 1
+            } else if lcs.delete_first(i, j) {
+                steps.push(Step::Delete);
+                i += 1
+            } else {
+                steps.push(Step::Add);
+                j += 1
+            }
+        }
+        steps.extend((i..rows - 1).map(|_| Step::Delete));
+        steps.extend((j..cols - 1).map(|_| Step::Add));
+        steps.extend((0..trailing_equals).map(|_| Step::Keep));
+        steps
+    }
+
+    fn lcs_length(a: &[&[u8]], b: &[&[u8]]) -> usize {
+        let mut opt = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                opt[i][j] = if a[i] == b[j] {
+                    opt[i + 1][j + 1] + 1
+                } else {
+                    ::std::cmp::max(opt[i + 1][j], opt[i][j + 1])
+                }
+            }
+        }
+        opt[0][0]
+    }
+
+    /// Checks that `steps` turns `a` into `b`.
+    fn check_steps(a: &[&[u8]], b: &[&[u8]], steps: &[Step]) {
+        let (mut i, mut j) = (0, 0);
+        for step in steps {
+            match *step {
+                Step::Keep => {
+                    assert_eq!(a[i], b[j], "{:?} {:?} {:?}", a, b, steps);
+                    i += 1;
+                    j += 1
+                }
+                Step::Delete => i += 1,
+                Step::Add => j += 1,
+            }
+        }
+        assert_eq!((i, j), (a.len(), b.len()), "{:?} {:?} {:?}", a, b, steps);
+    }
+
+    fn kept(steps: &[Step]) -> usize {
+        steps.iter().filter(|&&s| s == Step::Keep).count()
+    }
+
+    /// All the files of at most `n` lines taken among `lines`.
+    fn files<'a>(lines: &[&'a [u8]], n: usize) -> Vec<Vec<&'a [u8]>> {
+        let mut files = vec![Vec::new()];
+        let mut last = vec![Vec::new()];
+        for _ in 0..n {
+            let mut next = Vec::new();
+            for f in last.iter() {
+                for l in lines {
+                    let mut f: Vec<&[u8]> = f.clone();
+                    f.push(l);
+                    next.push(f)
+                }
+            }
+            files.extend(next.iter().cloned());
+            last = next
+        }
+        files
+    }
+
+    #[test]
+    fn myers_is_optimal() {
+        // When several alignments are optimal, Myers and the matrix
+        // may choose different ones, but they keep as many lines.
+        let lines: &[&[u8]] = &[b"a\n", b"b\n", b"c\n"];
+        let files = files(lines, 4);
+        for a in files.iter() {
+            for b in files.iter() {
+                let matrix = steps(a, b, None);
+                let myers = steps(a, b, Some(myers::lcs));
+                check_steps(a, b, &matrix);
+                check_steps(a, b, &myers);
+                assert_eq!(kept(&matrix), lcs_length(a, b), "{:?} {:?}", a, b);
+                assert_eq!(kept(&myers), lcs_length(a, b), "{:?} {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn myers_matches_matrix() {
+        let empty: &[&[u8]] = &[];
+        let same: &[&[u8]] = &[b"x\n", b"x\n", b"x\n"];
+        let more: &[&[u8]] = &[b"x\n", b"x\n", b"x\n", b"x\n", b"x\n"];
+        let a: &[&[u8]] = &[b"a\n", b"b\n", b"c\n", b"d\n", b"e\n"];
+        let inserted: &[&[u8]] = &[b"a\n", b"b\n", b"x\n", b"y\n", b"c\n", b"d\n", b"e\n"];
+        let deleted: &[&[u8]] = &[b"a\n", b"d\n", b"e\n"];
+        let replaced: &[&[u8]] = &[b"a\n", b"x\n", b"c\n", b"y\n", b"e\n"];
+        let cases = [(empty, empty), (empty, same), (same, empty), (same, same), (same, more),
+                     (more, same), (empty, a), (a, empty), (a, a), (a, inserted), (inserted, a),
+                     (a, deleted), (deleted, a), (a, replaced), (replaced, a),
+                     (inserted, replaced), (replaced, deleted)];
+        for &(a, b) in cases.iter() {
+            assert_eq!(steps(a, b, None), steps(a, b, Some(myers::lcs)), "{:?} {:?}", a, b)
+        }
+    }
+
+    #[test]
+    fn patience_is_a_common_subsequence() {
+        let lines: &[&[u8]] = &[b"a\n", b"b\n", b"c\n"];
+        let files = files(lines, 4);
+        for a in files.iter() {
+            for b in files.iter() {
+                let patience = steps(a, b, Some(patience::lcs));
+                check_steps(a, b, &patience);
+                assert!(kept(&patience) <= lcs_length(a, b))
+            }
+        }
+    }
+
+    #[test]
+    fn patience_unique_lines() {
+        // Without repeated lines, patience diff finds a longest
+        // common subsequence too.
+        let lines: &[&[u8]] = &[b"a\n", b"b\n", b"c\n", b"d\n"];
+        for a in files(lines, 4).iter().filter(|f| unique(f)) {
+            for b in files(lines, 4).iter().filter(|f| unique(f)) {
+                let patience = steps(a, b, Some(patience::lcs));
+                assert_eq!(kept(&patience), lcs_length(a, b), "{:?} {:?}", a, b)
+            }
+        }
+    }
+
+    #[test]
+    fn patience_moved_function() {
+        let a: &[&[u8]] = &[b"fn f() {\n", b"    1\n", b"}\n", b"fn g() {\n", b"    2\n", b"}\n"];
+        let b: &[&[u8]] = &[b"fn g() {\n", b"    2\n", b"}\n", b"fn f() {\n", b"    1\n", b"}\n"];
+        // `g` moved before `f`: `g` is kept, and `f` is deleted and
+        // added again after it, without splitting the functions.
+        assert_eq!(steps(a, b, Some(patience::lcs)),
+                   vec![Step::Delete, Step::Delete, Step::Delete, Step::Keep, Step::Keep,
+                        Step::Add, Step::Add, Step::Add, Step::Keep]);
+        let empty: &[&[u8]] = &[];
+        assert_eq!(steps(empty, empty, Some(patience::lcs)), vec![]);
+        assert_eq!(steps(empty, a, Some(patience::lcs)), vec![Step::Add; 6]);
+        assert_eq!(steps(a, a, Some(patience::lcs)), vec![Step::Keep; 6]);
+    }
+
+    fn unique(f: &[&[u8]]) -> bool {
+        f.iter().enumerate().all(|(i, l)| !f[i + 1..].contains(l))
+    }
+}
@@ -2,6 +2,10 @@ use backend::*;
 use patch::*;
 use record::InodeUpdate;
 use error::Error;
+use attributes::{Attributes, FileAttributes, MergeStrategy};
+use graph::{Graph, LineBuffer};
+use conflict;
+use sanakirja::value::Value;
 
 use rustc_serialize::hex::ToHex;
 use rustc_serialize::base64::{ToBase64, URL_SAFE};
@@ -9,7 +13,123 @@ use std::path::{Path, PathBuf};
 use std::collections::HashMap;
 use std;
 use std::fs;
+use std::io::Write;
 use rand;
+
+#[cfg(not(windows))]
+fn create_symlink(target: &[u8], name: &Path) -> Result<(), Error> {
+    use std::os::unix::ffi::OsStrExt;
+    try!(std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(target), name));
+    Ok(())
+}
+
+/// Windows needs to know whether the target is a directory, and
+/// creating links requires special privileges: write the target as a
+/// regular file if this fails.
+#[cfg(windows)]
+fn create_symlink(target: &[u8], name: &Path) -> Result<(), Error> {
+    use std::io::Write;
+    let target_str = String::from_utf8_lossy(target).replace('/', "\\");
+    let is_dir = name.parent()
+        .map(|p| p.join(&target_str).is_dir())
+        .unwrap_or(false);
+    let result = if is_dir {
+        std::os::windows::fs::symlink_dir(&target_str, name)
+    } else {
+        std::os::windows::fs::symlink_file(&target_str, name)
+    };
+    if result.is_err() {
+        let mut f = try!(fs::File::create(name));
+        try!(f.write_all(target));
+    }
+    Ok(())
+}
+
+/// Contents of a file being output, where conflict markers are
+/// skipped under the union merge strategy. The contents without the
+/// sides written between markers are kept too, for binary files.
+struct FileBuffer<'b> {
+    contents: &'b mut Vec<u8>,
+    markers: bool,
+    one_side: Vec<u8>,
+    in_conflict: bool,
+    conflicts: bool,
+}
+
+impl<'b> FileBuffer<'b> {
+    fn marker(&mut self, s: &str) {
+        if self.markers {
+            self.contents.extend(s.as_bytes())
+        }
+    }
+}
+
+impl<'a, 'b, A: 'a + Transaction> LineBuffer<'a, A> for FileBuffer<'b> {
+    fn output_line(&mut self, _: &Key<PatchId>, c: Value<A>) -> Result<(), Error> {
+        for chunk in c {
+            self.contents.extend(chunk);
+            if !self.in_conflict {
+                self.one_side.extend(chunk)
+            }
+        }
+        Ok(())
+    }
+
+    fn output_conflict_marker(&mut self, s: &'a str) -> Result<(), Error> {
+        self.marker(s);
+        Ok(())
+    }
+
+    fn begin_conflict(&mut self) -> Result<(), Error> {
+        self.in_conflict = true;
+        self.conflicts = true;
+        self.marker(conflict::START_MARKER);
+        Ok(())
+    }
+
+    fn end_conflict(&mut self) -> Result<(), Error> {
+        self.in_conflict = false;
+        self.marker(conflict::END_MARKER);
+        Ok(())
+    }
+}
+
+impl<A: Transaction, R> T<A, R> {
+    /// Appends the contents of the file retrieved in `graph` to
+    /// `contents`, writing conflicts as specified by `attributes`.
+    /// In binary files, which markers would corrupt, only the side
+    /// of each conflict written before the markers is kept. Line
+    /// endings are not converted.
+    pub fn output_file_contents(&self,
+                                graph: &mut Graph,
+                                attributes: &FileAttributes,
+                                contents: &mut Vec<u8>,
+                                forward: &mut Vec<(Key<PatchId>, Edge)>)
+                                -> Result<(), Error> {
+        let start = contents.len();
+        let one_side = {
+            let mut buf = FileBuffer {
+                contents: contents,
+                markers: attributes.merge != Some(MergeStrategy::Union),
+                one_side: Vec::new(),
+                in_conflict: false,
+                conflicts: false,
+            };
+            self.output_file(&mut buf, graph, forward)?;
+            if buf.conflicts && !attributes.is_text(&buf.one_side) {
+                Some(buf.one_side)
+            } else {
+                None
+            }
+        };
+        if let Some(one_side) = one_side {
+            contents.truncate(start);
+            contents.extend(one_side)
+        }
+        Ok(())
+    }
+}
+
 impl<'env, T: rand::Rng> MutTxn<'env, T> {
     // Climp up the tree (using revtree).
     fn filename_of_inode(&self, inode: &Inode, working_copy: &Path) -> Option<PathBuf> {
@@ -118,11 +238,13 @@ impl<'env, T: rand::Rng> MutTxn<'env, T> {
     fn output_repository_assuming_no_pending_patch(&mut self,
                                                    branch: &Branch,
                                                    working_copy: &Path,
-                                                   pending_patch_id: PatchId)
+                                                   pending_patch_id: PatchId,
+                                                   defaults: &FileAttributes)
                                                    -> Result<(), Error> {
 
 
         {
+            let attributes = Attributes::load(working_copy, defaults)?;
             let mut files = HashMap::new();
             let mut next_files = HashMap::new();
             self.collect_children(branch, working_copy, &ROOT_KEY, &ROOT_INODE, &mut files);
@@ -184,10 +306,28 @@ impl<'env, T: rand::Rng> MutTxn<'env, T> {
                             // Output file.
                             let mut redundant_edges = Vec::new();
                             let mut l = self.retrieve(branch, &key);
-                            debug!("creating file {:?}", &name);
-                            let mut f = std::fs::File::create(&name).unwrap();
-                            debug!("done");
-                            try!(self.output_file(&mut f, &mut l, &mut redundant_edges));
+                            // Don't write through an existing link.
+                            if let Ok(m) = fs::symlink_metadata(&name) {
+                                if m.file_type().is_symlink() {
+                                    try!(fs::remove_file(&name))
+                                }
+                            }
+                            if meta.is_symlink() {
+                                debug!("creating link {:?}", &name);
+                                let mut target = Vec::new();
+                                try!(self.output_file(&mut target, &mut l, &mut redundant_edges));
+                                try!(create_symlink(&target, &name));
+                            } else {
+                                debug!("creating file {:?}", &name);
+                                let attributes = attributes.get_real(&a);
+                                let mut contents = Vec::new();
+                                self.output_file_contents(&mut l, &attributes, &mut contents,
+                                                          &mut redundant_edges)?;
+                                attributes.convert_eol(&mut contents);
+                                let mut f = std::fs::File::create(&name)?;
+                                debug!("done");
+                                f.write_all(&contents)?;
+                            }
                         }
                     }
                 }
@@ -232,7 +372,7 @@ impl<'env, T: rand::Rng> MutTxn<'env, T> {
             debug!("removed");
             if let Some(ref name) = *name {
                 debug!("deleting {:?}", name);
-                let meta = try!(fs::metadata(name));
+                let meta = try!(fs::symlink_metadata(name));
                 if meta.is_dir() {
                     try!(fs::remove_dir_all(name))
                 } else {
@@ -283,7 +423,8 @@ impl<'env, T: rand::Rng> MutTxn<'env, T> {
                              branch_name: &str,
                              working_copy: &Path,
                              pending: &Patch,
-                             local_pending: &[InodeUpdate])
+                             local_pending: &[InodeUpdate],
+                             defaults: &FileAttributes)
                              -> Result<(), Error> {
         debug!("begin output repository");
 
@@ -292,7 +433,8 @@ impl<'env, T: rand::Rng> MutTxn<'env, T> {
 
         debug!("applied");
         let mut branch = try!(self.open_branch(branch_name));
-        try!(self.output_repository_assuming_no_pending_patch(&branch, working_copy, internal));
+        try!(self.output_repository_assuming_no_pending_patch(&branch, working_copy, internal,
+                                                               defaults));
 
         debug!("unrecording pending patch");
         self.unrecord(&mut branch, &internal, pending)?;
@@ -9,7 +9,7 @@ use std::path::Path;
 use std::io::{Read, BufRead, Write};
 use std::fs::{File, metadata};
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::str::from_utf8;
 use std::path::PathBuf;
 use std::rc::Rc;
@@ -21,7 +21,7 @@ use bincode::{deserialize, deserialize_from, serialize_into, serialize, Infinite
 
 pub const PATCH_FORMAT_VERSION: u64 = 0;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Patch {
     pub version: u64,
     pub header: PatchHeader,
@@ -29,7 +29,7 @@ pub struct Patch {
     pub changes: Vec<Change>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PatchHeader {
     pub authors: Vec<String>,
     pub name: String,
@@ -217,6 +217,47 @@ impl Patch {
         size
     }
 
+    /// Tells whether this patch, whose hash is `hash`, adds, deletes
+    /// or uses as a context one of the nodes of `keys`, as returned
+    /// by `path_history_keys`. If so, the nodes it adds in the
+    /// context of `keys` are added to `keys`, so that later patches
+    /// editing them are found too.
+    pub fn touches_keys(&self, hash: &Hash, keys: &mut HashSet<Key<Hash>>) -> bool {
+        let external = |k: &Key<Option<Hash>>| {
+            Key {
+                patch: k.patch.as_ref().unwrap_or(hash).clone(),
+                line: k.line.clone(),
+            }
+        };
+        let mut touches = keys.iter().any(|k| k.patch == *hash);
+        loop {
+            let mut added = false;
+            for change in self.changes.iter() {
+                match *change {
+                    Change::NewNodes { ref up_context, ref down_context, ref line_num, ref nodes, .. } => {
+                        if up_context.iter().chain(down_context.iter()).any(|k| keys.contains(&external(k))) {
+                            touches = true;
+                            for i in 0..nodes.len() {
+                                added |= keys.insert(Key {
+                                    patch: hash.clone(),
+                                    line: *line_num + i,
+                                })
+                            }
+                        }
+                    }
+                    Change::NewEdges { ref edges, .. } => {
+                        touches |= edges.iter().any(|e| {
+                            keys.contains(&external(&e.from)) || keys.contains(&external(&e.to))
+                        })
+                    }
+                }
+            }
+            if !added {
+                return touches
+            }
+        }
+    }
+
     pub fn from_reader_compressed<R: BufRead>(r: &mut R) -> Result<(Hash, Vec<u8>, Patch), Error> {
         let mut rr = flate2::bufread::GzDecoder::new(r)?;
         let filename = Hash::from_base64(from_utf8(rr.header().filename().unwrap())?).unwrap();
@@ -467,3 +508,256 @@ impl<A: sanakirja::Transaction, R> T<A, R> {
         result
     }
 }
+
+/// Placeholder for the files of changes that cannot be located in a
+/// branch, for instance because they depend on unknown patches.
+const UNKNOWN_FILE: &'static str = "<unknown file>";
+
+fn basename_of(name: &[u8]) -> String {
+    if name.len() >= 2 {
+        String::from_utf8_lossy(&name[2..]).into_owned()
+    } else {
+        String::new()
+    }
+}
+
+impl<A: sanakirja::Transaction, R> T<A, R> {
+    /// The internal version of `key`, if it is not a key introduced
+    /// by the patch it appears in, and is known in this repository.
+    pub fn internal_key_opt(&self, key: &Key<Option<Hash>>) -> Option<Key<PatchId>> {
+        match key.patch {
+            Some(Hash::None) => Some(Key {
+                patch: ROOT_PATCH_ID.clone(),
+                line: key.line.clone(),
+            }),
+            Some(ref h) => self.get_internal(h.as_ref()).map(|p| Key {
+                patch: p,
+                line: key.line.clone(),
+            }),
+            None => None,
+        }
+    }
+
+    /// A parent of `key` along an edge with flag `flag`, preferring
+    /// alive edges to deleted ones.
+    fn any_parent(&self, branch: &Branch, key: &Key<PatchId>, flag: EdgeFlags) -> Option<Key<PatchId>> {
+        for &f in &[flag | PARENT_EDGE, flag | PARENT_EDGE | DELETED_EDGE] {
+            let edge = Edge::zero(f);
+            if let Some((k, v)) = self.iter_nodes(branch, Some((key, Some(&edge)))).next() {
+                if *k == *key && v.flag - PSEUDO_EDGE == f {
+                    return Some(v.dest.clone())
+                }
+            }
+        }
+        None
+    }
+
+    /// The node of the file containing line `key`.
+    pub fn find_file_node(&self, branch: &Branch, key: &Key<PatchId>) -> Option<Key<PatchId>> {
+        let mut key = key.clone();
+        let mut visited = HashSet::new();
+        while visited.insert(key.clone()) {
+            if key == ROOT_KEY || self.any_parent(branch, &key, FOLDER_EDGE).is_some() {
+                return Some(key)
+            }
+            match self.any_parent(branch, &key, EdgeFlags::empty()) {
+                Some(parent) => key = parent,
+                None => return None,
+            }
+        }
+        None
+    }
+
+    /// The path of file (or directory) node `key`, following deleted
+    /// names if the file has been deleted.
+    pub fn node_path(&self, branch: &Branch, key: &Key<PatchId>) -> Option<PathBuf> {
+        let mut names = Vec::new();
+        let mut key = key.clone();
+        let mut visited = HashSet::new();
+        while key != ROOT_KEY {
+            if !visited.insert(key.clone()) {
+                return None
+            }
+            let name_node = match self.any_parent(branch, &key, FOLDER_EDGE) {
+                Some(name_node) => name_node,
+                None => return None,
+            };
+            match self.get_contents(&name_node) {
+                Some(name) => names.push(basename_of(&name.into_cow())),
+                None => return None,
+            }
+            key = match self.any_parent(branch, &name_node, FOLDER_EDGE) {
+                Some(parent) => parent,
+                None => return None,
+            };
+        }
+        Some(names.iter().rev().collect())
+    }
+
+    /// The path of the directory or file node `key`, which is either
+    /// in `branch`, or in `local` if it was introduced by the current
+    /// patch.
+    fn external_node_path(&self,
+                          branch: &Branch,
+                          local: &HashMap<LineId, Rc<PathBuf>>,
+                          key: &Key<Option<Hash>>)
+                          -> Rc<PathBuf> {
+        let path = if key.patch.is_none() {
+            local.get(&key.line).cloned()
+        } else {
+            self.internal_key_opt(key)
+                .and_then(|key| self.node_path(branch, &key))
+                .map(Rc::new)
+        };
+        path.unwrap_or_else(|| Rc::new(PathBuf::from(UNKNOWN_FILE)))
+    }
+
+    /// The path of the file containing line `key`.
+    fn external_line_path(&self,
+                          branch: &Branch,
+                          local: &HashMap<LineId, Rc<PathBuf>>,
+                          key: &Key<Option<Hash>>)
+                          -> Rc<PathBuf> {
+        let path = if key.patch.is_none() {
+            local.get(&key.line).cloned()
+        } else {
+            self.internal_key_opt(key)
+                .and_then(|key| self.find_file_node(branch, &key))
+                .and_then(|key| self.node_path(branch, &key))
+                .map(Rc::new)
+        };
+        path.unwrap_or_else(|| Rc::new(PathBuf::from(UNKNOWN_FILE)))
+    }
+
+    /// The former path of the file whose names are deleted by `edges`.
+    fn deleted_name_path(&self,
+                         branch: &Branch,
+                         local: &HashMap<LineId, Rc<PathBuf>>,
+                         edges: &[NewEdge])
+                         -> Rc<PathBuf> {
+        for e in edges {
+            if let Some(name) = self.internal_key_opt(&e.from).and_then(|k| self.get_contents(&k)) {
+                let name = name.into_cow();
+                if name.len() >= 2 {
+                    let dir = self.external_node_path(branch, local, &e.to);
+                    return Rc::new(dir.join(basename_of(&name)))
+                }
+            }
+        }
+        Rc::new(PathBuf::from(UNKNOWN_FILE))
+    }
+
+    /// The path of a name introduced by a `NewNodes` change with
+    /// flag `FOLDER_EDGE`.
+    fn new_name_path(&self,
+                     branch: &Branch,
+                     local: &HashMap<LineId, Rc<PathBuf>>,
+                     up_context: &[Key<Option<Hash>>],
+                     nodes: &[Vec<u8>])
+                     -> Rc<PathBuf> {
+        let dir = match up_context.get(0) {
+            Some(up) => self.external_node_path(branch, local, up),
+            None => Rc::new(PathBuf::new()),
+        };
+        let name = nodes.get(0).map(|n| basename_of(n)).unwrap_or(String::new());
+        Rc::new(dir.join(name))
+    }
+
+    /// Groups the changes of `patch` by file, in the same way as
+    /// `record` does, using `branch` to find the files touched by the
+    /// patch. The patch does not need to be applied to `branch`.
+    pub fn patch_records(&self, branch: &Branch, patch: &Patch) -> Vec<Record> {
+        // Paths of the file and directory nodes introduced by this
+        // patch, and of the files of the lines it introduces.
+        let mut local: HashMap<LineId, Rc<PathBuf>> = HashMap::new();
+        let mut records = Vec::new();
+        let mut changes = patch.changes.iter().peekable();
+        while let Some(change) = changes.next() {
+            match *change {
+                Change::NewNodes { ref up_context, ref down_context, ref flag, ref line_num, ref nodes } => {
+                    if flag.contains(FOLDER_EDGE) {
+                        let path = self.new_name_path(branch, &local, up_context, nodes);
+                        let name = path.to_string_lossy().into_owned();
+                        if down_context.is_empty() {
+                            // The name node is followed by the node of the file itself.
+                            local.insert(*line_num + 1, path);
+                            records.push(Record::FileAdd {
+                                name: name,
+                                add: change.clone(),
+                            })
+                        } else {
+                            // A new name for an existing file, whose
+                            // former names are not deleted.
+                            records.push(Record::FileMove {
+                                new_name: name,
+                                del: Change::NewEdges {
+                                    edges: Vec::new(),
+                                    op: EdgeOp::Map {
+                                        previous: FOLDER_EDGE | PARENT_EDGE,
+                                        flag: DELETED_EDGE | FOLDER_EDGE | PARENT_EDGE,
+                                    },
+                                },
+                                add: change.clone(),
+                            })
+                        }
+                    } else {
+                        let file = match (up_context.get(0), down_context.get(0)) {
+                            (Some(k), _) | (None, Some(k)) => self.external_line_path(branch, &local, k),
+                            (None, None) => Rc::new(PathBuf::from(UNKNOWN_FILE)),
+                        };
+                        for i in 0..nodes.len() {
+                            local.insert(*line_num + i, file.clone());
+                        }
+                        records.push(Record::Change {
+                            file: file,
+                            change: change.clone(),
+                            conflict_reordering: Vec::new(),
+                        })
+                    }
+                }
+                Change::NewEdges { ref edges, ref op } => {
+                    let flag = match *op {
+                        EdgeOp::Map { flag, .. } | EdgeOp::New { flag } => flag,
+                        EdgeOp::Forget { previous } => previous,
+                    };
+                    if flag.contains(FOLDER_EDGE) {
+                        // Deleting the names of a file is either a
+                        // deletion, or the first half of a move.
+                        let new_name = match changes.peek() {
+                            Some(&&Change::NewNodes { ref flag, ref down_context, ref up_context, ref nodes, .. })
+                                if flag.contains(FOLDER_EDGE) && !down_context.is_empty() => {
+                                    Some(self.new_name_path(branch, &local, up_context, nodes))
+                                }
+                            _ => None,
+                        };
+                        if let Some(new_name) = new_name {
+                            let add = changes.next().unwrap();
+                            records.push(Record::FileMove {
+                                new_name: new_name.to_string_lossy().into_owned(),
+                                del: change.clone(),
+                                add: add.clone(),
+                            })
+                        } else {
+                            let name = self.deleted_name_path(branch, &local, edges);
+                            records.push(Record::FileDel {
+                                name: name.to_string_lossy().into_owned(),
+                                del: change.clone(),
+                            })
+                        }
+                    } else {
+                        let file = match edges.get(0) {
+                            Some(e) => self.external_line_path(branch, &local, &e.from),
+                            None => Rc::new(PathBuf::from(UNKNOWN_FILE)),
+                        };
+                        records.push(Record::Change {
+                            file: file,
+                            change: change.clone(),
+                            conflict_reordering: Vec::new(),
+                        })
+                    }
+                }
+            }
+        }
+        records
+    }
+}
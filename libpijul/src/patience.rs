@@ -0,0 +1,100 @@
+//! Patience diff: lines that appear exactly once in both versions are
+//! matched first, keeping the longest increasing sequence of such
+//! pairs, and the gaps between them are aligned recursively. This
+//! keeps unique lines, such as function signatures, aligned together
+//! instead of blank lines and closing braces.
+
+use myers;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Sets `matches[i]` to `Some(j)` if `a[i]` is matched with `b[j]`,
+/// leaving the other elements of `matches` untouched.
+pub fn lcs<T: Eq + Hash>(a: &[T], b: &[T], matches: &mut [Option<usize>]) {
+    patience(a, 0, a.len(), b, 0, b.len(), matches)
+}
+
+fn patience<T: Eq + Hash>(a: &[T],
+                          mut a0: usize,
+                          mut a1: usize,
+                          b: &[T],
+                          mut b0: usize,
+                          mut b1: usize,
+                          matches: &mut [Option<usize>]) {
+    while a0 < a1 && b0 < b1 && a[a0] == b[b0] {
+        matches[a0] = Some(b0);
+        a0 += 1;
+        b0 += 1;
+    }
+    while a0 < a1 && b0 < b1 && a[a1 - 1] == b[b1 - 1] {
+        matches[a1 - 1] = Some(b1 - 1);
+        a1 -= 1;
+        b1 -= 1;
+    }
+    if a0 == a1 || b0 == b1 {
+        return
+    }
+    let anchors = {
+        // For each line, its number of occurrences and last position
+        // in both ranges.
+        let mut occurrences: HashMap<&T, (usize, usize, usize, usize)> = HashMap::new();
+        for i in a0..a1 {
+            let e = occurrences.entry(&a[i]).or_insert((0, 0, 0, 0));
+            e.0 += 1;
+            e.1 = i;
+        }
+        for j in b0..b1 {
+            if let Some(e) = occurrences.get_mut(&b[j]) {
+                e.2 += 1;
+                e.3 = j;
+            }
+        }
+        let mut unique: Vec<(usize, usize)> = occurrences.values()
+            .filter(|&&(na, _, nb, _)| na == 1 && nb == 1)
+            .map(|&(_, i, _, j)| (i, j))
+            .collect();
+        unique.sort();
+        longest_increasing(&unique)
+    };
+    if anchors.is_empty() {
+        myers::lcs_range(a, a0, a1, b, b0, b1, matches);
+        return
+    }
+    for (i, j) in anchors {
+        patience(a, a0, i, b, b0, j, matches);
+        matches[i] = Some(j);
+        a0 = i + 1;
+        b0 = j + 1;
+    }
+    patience(a, a0, a1, b, b0, b1, matches)
+}
+
+/// Longest subsequence of `pairs` (sorted by their first component)
+/// whose second components are increasing, by patience sorting.
+fn longest_increasing(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // `tops[k]` is the index in `pairs` of the smallest possible end
+    // of an increasing subsequence of length `k + 1`.
+    let mut tops: Vec<usize> = Vec::new();
+    let mut previous = vec![None; pairs.len()];
+    for (n, &(_, j)) in pairs.iter().enumerate() {
+        let pile = match tops.binary_search_by(|&t| pairs[t].1.cmp(&j)) {
+            Ok(pile) | Err(pile) => pile,
+        };
+        if pile > 0 {
+            previous[n] = Some(tops[pile - 1])
+        }
+        if pile == tops.len() {
+            tops.push(n)
+        } else {
+            tops[pile] = n
+        }
+    }
+    let mut result = Vec::with_capacity(tops.len());
+    let mut current = tops.last().cloned();
+    while let Some(n) = current {
+        result.push(pairs[n]);
+        current = previous[n]
+    }
+    result.reverse();
+    result
+}
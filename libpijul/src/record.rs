@@ -3,9 +3,11 @@ use patch::*;
 use error::*;
 use graph;
 use optimal_diff;
+use chunks;
+use attributes::{Attributes, FileAttributes};
 
 use std::path::{Path, PathBuf};
-use std::fs::metadata;
+use std::fs::symlink_metadata;
 use std;
 use std::io::BufRead;
 use rustc_serialize::hex::ToHex;
@@ -26,17 +28,100 @@ fn permissions(_: &std::fs::Metadata) -> Option<usize> {
 
 
 fn file_metadata(path: &Path) -> Result<FileMetadata, Error> {
-    let attr = metadata(&path)?;
+    let attr = symlink_metadata(&path)?;
     let permissions = permissions(&attr).unwrap_or(0o755);
-    Ok(FileMetadata::new(permissions, attr.is_dir()))
+    if attr.file_type().is_symlink() {
+        Ok(FileMetadata::new_symlink(permissions))
+    } else {
+        Ok(FileMetadata::new(permissions, attr.is_dir()))
+    }
+}
+
+#[cfg(not(windows))]
+fn link_target(path: &Path) -> Result<Vec<u8>, Error> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(std::fs::read_link(path)?.as_os_str().as_bytes().to_vec())
+}
+#[cfg(windows)]
+fn link_target(path: &Path) -> Result<Vec<u8>, Error> {
+    let target = std::fs::read_link(path)?;
+    Ok(target.to_string_lossy().replace('\\', "/").into_bytes())
+}
+
+/// Reads the contents of a file of the working copy into `contents`.
+/// The contents of a symbolic link is its target, not the contents of
+/// the file it points to.
+fn read_contents(path: &Path, meta: FileMetadata, contents: &mut Vec<u8>) -> Result<(), Error> {
+    if meta.is_symlink() {
+        contents.extend(link_target(path)?)
+    } else {
+        let mut f = std::fs::File::open(path)?;
+        f.read_to_end(contents)?;
+    }
+    Ok(())
+}
+
+/// The stamp of a file, from its metadata, as compared with `branch`.
+#[cfg(not(windows))]
+fn file_stamp(branch: &Branch, attr: &std::fs::Metadata) -> FileStamp {
+    use std::os::unix::fs::MetadataExt;
+    FileStamp {
+        branch: branch_hash(branch),
+        mtime: attr.mtime(),
+        mtime_nsec: attr.mtime_nsec() as u32,
+        size: attr.size(),
+        ino: attr.ino(),
+    }
+}
+#[cfg(windows)]
+fn file_stamp(branch: &Branch, attr: &std::fs::Metadata) -> FileStamp {
+    let mtime = attr.modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok());
+    FileStamp {
+        branch: branch_hash(branch),
+        mtime: mtime.map(|d| d.as_secs() as i64).unwrap_or(0),
+        mtime_nsec: mtime.map(|d| d.subsec_nanos()).unwrap_or(0),
+        size: attr.len(),
+        ino: 0,
+    }
+}
+
+/// FNV-1a hash of the name of a branch.
+fn branch_hash(branch: &Branch) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for &b in branch.name.as_str().as_bytes() {
+        h = (h ^ b as u64).wrapping_mul(0x100000001b3)
+    }
+    h
 }
 
+/// A file modified in the last couple of seconds could be modified
+/// again without changing its mtime, in which case its stamp must not
+/// be trusted.
+fn is_settled(stamp: &FileStamp) -> bool {
+    let mtime = stamp.mtime;
+    match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        Ok(now) => now.as_secs() as i64 - mtime >= 2,
+        Err(_) => false,
+    }
+}
 
 struct RecordState {
     line_num: LineId,
     updatables: Vec<InodeUpdate>,
     actions: Vec<Record>,
     redundant: Vec<(Key<PatchId>, Edge)>,
+    /// Root of the working copy.
+    repo_root: PathBuf,
+    attributes: Attributes,
+    /// If non-empty, only the files under these paths (relative to
+    /// the root of the repository) are examined.
+    prefixes: Vec<PathBuf>,
+    /// Read all files, even those whose stamp has not changed.
+    full_check: bool,
+    /// Stamps of the files found unchanged.
+    stamps: Vec<(Inode, FileStamp)>,
 }
 
 #[derive(Debug)]
@@ -56,10 +141,6 @@ pub enum WorkingFileStatus {
     Ok,
 }
 
-fn is_text(x: &[u8]) -> bool {
-    x.iter().take(8000).all(|&c| c != 0)
-}
-
 impl<A: Transaction, R: rand::Rng> T<A, R> {
     /// Create appropriate NewNodes for adding a file.
     fn record_file_addition(&self,
@@ -114,13 +195,12 @@ impl<A: Transaction, R: rand::Rng> T<A, R> {
         if !meta.is_dir() {
             nodes.clear();
 
+            let attributes = st.attributes.get_real(&realpath);
             let mut node = Vec::new();
-            {
-                let mut f = std::fs::File::open(realpath.as_path())?;
-                f.read_to_end(&mut node)?;
-            }
+            read_contents(realpath.as_path(), meta, &mut node)?;
 
-            if is_text(&node) {
+            if attributes.is_text(&node) {
+                attributes.normalize_eol(&mut node);
                 let mut line = Vec::new();
                 let mut f = &node[..];
                 loop {
@@ -136,25 +216,11 @@ impl<A: Transaction, R: rand::Rng> T<A, R> {
                         Err(_) => break,
                     }
                 }
-                let len = nodes.len();
-                if !nodes.is_empty() {
-                    st.actions.push(Record::Change {
-                        change: Change::NewNodes {
-                            up_context: vec![Key {
-                                                 patch: None,
-                                                 line: blank_line_num.clone(),
-                                             }],
-                            line_num: st.line_num,
-                            down_context: vec![],
-                            nodes: nodes,
-                            flag: EdgeFlags::empty(),
-                        },
-                        file: Rc::new(realpath.clone()),
-                        conflict_reordering: Vec::new(),
-                    });
-                }
-                st.line_num += len;
             } else {
+                nodes.extend(chunks::read_chunks(&node).into_iter().map(|chunk| chunk.to_vec()))
+            }
+            let len = nodes.len();
+            if !nodes.is_empty() {
                 st.actions.push(Record::Change {
                     change: Change::NewNodes {
                         up_context: vec![Key {
@@ -163,39 +229,39 @@ impl<A: Transaction, R: rand::Rng> T<A, R> {
                                          }],
                         line_num: st.line_num,
                         down_context: vec![],
-                        nodes: vec![node],
+                        nodes: nodes,
                         flag: EdgeFlags::empty(),
                     },
                     file: Rc::new(realpath.clone()),
                     conflict_reordering: Vec::new(),
                 });
-                st.line_num += 1;
             }
+            st.line_num += len;
             Ok(None)
         } else {
             Ok(Some(blank_line_num))
         }
     }
 
-    /// Diff for binary files, doesn't both splitting the file in
-    /// lines. This is wasteful, but doesn't break the format, and
-    /// doesn't create conflicts inside binary files.
+    /// Diff a file with its recorded version. Binary files are not
+    /// split in lines, but in chunks (see `chunks::read_chunks`), so
+    /// that small edits produce small patches.
     fn diff_with_binary(&self,
                         branch: &Branch,
                         st: &mut RecordState,
                         ret: &mut graph::Graph,
-                        path: Rc<PathBuf>)
+                        path: Rc<PathBuf>,
+                        meta: FileMetadata)
                         -> Result<(), Error> {
 
+        let attributes = st.attributes.get_real(path.as_ref());
         let mut lines_b = Vec::new();
-        {
-            let mut f = std::fs::File::open(path.as_ref())?;
-            f.read_to_end(&mut lines_b)?;
-        }
-        let lines = if is_text(&lines_b) {
+        read_contents(path.as_ref(), meta, &mut lines_b)?;
+        let lines = if attributes.is_text(&lines_b) {
+            attributes.normalize_eol(&mut lines_b);
             optimal_diff::read_lines(&lines_b)
         } else {
-            vec![&lines_b[..]]
+            chunks::read_chunks(&lines_b)
         };
 
         self.diff(branch,
@@ -204,7 +270,8 @@ impl<A: Transaction, R: rand::Rng> T<A, R> {
                   &mut st.actions,
                   &mut st.redundant,
                   ret,
-                  &lines)
+                  &lines,
+                  attributes.diff.unwrap_or_default())
     }
 
     fn record_moved_file(&self,
@@ -300,7 +367,7 @@ impl<A: Transaction, R: rand::Rng> T<A, R> {
             info!("retrieving");
             let mut ret = self.retrieve(branch, current_node);
             debug!("diff");
-            try!(self.diff_with_binary(branch, st, &mut ret, Rc::new(realpath.clone())));
+            try!(self.diff_with_binary(branch, st, &mut ret, Rc::new(realpath.clone()), new_meta));
         };
         Ok(())
     }
@@ -417,35 +484,20 @@ impl<A: Transaction, R: rand::Rng> T<A, R> {
         Ok(())
     }
 
-    fn inode_status(&self, inode : &Inode, path: &Path)
-                    -> (Option<(WorkingFileStatus, FileHeader)>) {
-        match self.get_inodes(inode) {
-            Some(file_header) => {
-                let old_meta = file_header.metadata;
-                let new_meta = file_metadata(path).ok();
-
-                debug!("current_node={:?}", file_header);
-                debug!("old_attr={:?},int_attr={:?}", old_meta, new_meta);
-
-                let status =
-                    match (new_meta, file_header.status) {
-                        (Some(new_meta), FileStatus::Moved) =>
-                            WorkingFileStatus::Moved { from: old_meta, to: new_meta },
-                        (Some(new_meta), _) if old_meta != new_meta =>
-                            WorkingFileStatus::Moved { from: old_meta, to: new_meta },
-                        (None, _) |
-                        (_, FileStatus::Deleted) =>
-                            WorkingFileStatus::Deleted,
-                        (Some(_), FileStatus::Ok) =>
-                            WorkingFileStatus::Ok,
-                    };
-                Some((status, file_header.clone()))
-            },
-            None => None
+    /// Tells whether `realpath` must be examined, i.e. whether it is
+    /// under one of the prefixes, or one of its ancestors.
+    fn is_in_prefixes(&self, st: &RecordState, realpath: &Path) -> bool {
+        if st.prefixes.is_empty() {
+            return true
+        }
+        match realpath.strip_prefix(&st.repo_root) {
+            Ok(relative) => {
+                st.prefixes.iter().any(|p| relative.starts_with(p) || p.starts_with(relative))
+            }
+            Err(_) => true,
         }
     }
 
-
     fn record_inode(&self,
                     branch: &Branch,
                     st: &mut RecordState,
@@ -455,6 +507,10 @@ impl<A: Transaction, R: rand::Rng> T<A, R> {
                     basename: &str)
                     -> Result<(), Error> {
         realpath.push(basename);
+        if !self.is_in_prefixes(st, realpath) {
+            realpath.pop();
+            return Ok(())
+        }
         debug!("realpath: {:?}", realpath);
         debug!("inode: {:?}", current_inode);
         debug!("header: {:?}", self.get_inodes(current_inode));
@@ -480,11 +536,22 @@ impl<A: Transaction, R: rand::Rng> T<A, R> {
                 self.record_deleted_file(st, branch, realpath, &file_header.key)?
             },
             Some((WorkingFileStatus::Ok, file_header)) => {
-                if !file_header.metadata.is_dir() {
+                if !file_header.metadata.is_dir() &&
+                    (st.full_check || !self.has_valid_stamp(branch, current_inode, realpath)) {
+                    // Take the stamp before reading the file, so that
+                    // concurrent modifications invalidate it.
+                    let stamp = symlink_metadata(&realpath).map(|attr| file_stamp(branch, &attr));
+                    let n_actions = st.actions.len();
                     let mut ret = self.retrieve(branch, &file_header.key);
                     info!("now calling diff {:?}", file_header.key);
                     debug!("ret = {:?}", ret);
-                    self.diff_with_binary(branch, st, &mut ret, Rc::new(realpath.clone()))?;
+                    self.diff_with_binary(branch, st, &mut ret, Rc::new(realpath.clone()),
+                                          file_header.metadata)?;
+                    if let Ok(stamp) = stamp {
+                        if st.actions.len() == n_actions && is_settled(&stamp) {
+                            st.stamps.push((current_inode.clone(), stamp))
+                        }
+                    }
                 }
             },
             None => {
@@ -516,6 +583,11 @@ impl<A: Transaction, R: rand::Rng> T<A, R> {
             actions: Vec::new(),
             updatables: Vec::new(),
             redundant: Vec::new(),
+            repo_root: repo_root.to_path_buf(),
+            attributes: Attributes::load(repo_root, &FileAttributes::default())?,
+            prefixes: Vec::new(),
+            full_check: false,
+            stamps: Vec::new(),
         };
         let mut repo_root = repo_root.to_owned();
         self.record_root(&branch, &mut st, &mut repo_root)?;
@@ -525,28 +597,257 @@ impl<A: Transaction, R: rand::Rng> T<A, R> {
     }
 }
 
-impl<'env, T: rand::Rng> MutTxn<'env, T> {
-    pub fn record(&mut self,
-                  branch_name: &str,
-                  working_copy: &std::path::Path)
-                  -> Result<(Vec<Record>, Vec<InodeUpdate>), Error> {
+/// The kind of difference between a file of the working copy and
+/// its recorded version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatusKind {
+    /// Added with `pijul add`, and not yet recorded.
+    Added,
+    /// Removed with `pijul remove`, and not yet recorded.
+    Deleted,
+    /// Tracked, but absent from the working copy.
+    Missing,
+    /// Moved or renamed, from the given path.
+    Moved { from: PathBuf },
+    /// Contents or permissions changed.
+    Modified,
+    /// Present in the working copy but neither tracked nor ignored.
+    Untracked,
+}
 
-        let branch = try!(self.open_branch(branch_name));
+/// The status of a file of the working copy.
+#[derive(Debug, Clone)]
+pub struct PathStatus {
+    /// Path of the file, relative to the root of the repository.
+    pub path: PathBuf,
+    pub kind: StatusKind,
+}
+
+impl<A: Transaction, R> T<A, R> {
+    fn inode_status(&self, inode : &Inode, path: &Path)
+                    -> (Option<(WorkingFileStatus, FileHeader)>) {
+        match self.get_inodes(inode) {
+            Some(file_header) => {
+                let old_meta = file_header.metadata;
+                let new_meta = file_metadata(path).ok();
+
+                debug!("current_node={:?}", file_header);
+                debug!("old_attr={:?},int_attr={:?}", old_meta, new_meta);
+
+                let status =
+                    match (new_meta, file_header.status) {
+                        (Some(new_meta), FileStatus::Moved) =>
+                            WorkingFileStatus::Moved { from: old_meta, to: new_meta },
+                        (Some(new_meta), _) if old_meta != new_meta =>
+                            WorkingFileStatus::Moved { from: old_meta, to: new_meta },
+                        (None, _) |
+                        (_, FileStatus::Deleted) =>
+                            WorkingFileStatus::Deleted,
+                        (Some(_), FileStatus::Ok) =>
+                            WorkingFileStatus::Ok,
+                    };
+                Some((status, file_header.clone()))
+            },
+            None => None
+        }
+    }
+
+    /// Tells whether the stamp of `realpath` is the one stored for
+    /// `inode`, meaning that the file has not changed since it was
+    /// last compared with `branch`.
+    fn has_valid_stamp(&self, branch: &Branch, inode: &Inode, realpath: &Path) -> bool {
+        match (self.get_stamps(inode), symlink_metadata(realpath)) {
+            (Some(stamp), Ok(attr)) => stamp == file_stamp(branch, &attr),
+            _ => false,
+        }
+    }
+
+    /// Compares each file of the working copy rooted at `repo_root`
+    /// with its version in `branch`. Unchanged files are not
+    /// included in the result, which is sorted by path. A file both
+    /// moved and modified appears twice.
+    ///
+    /// Unless `full_check` is true, files with a valid stamp are
+    /// assumed unchanged. `defaults` are the attributes of files not
+    /// set by the attributes file.
+    pub fn status(&self,
+                  branch: &Branch,
+                  repo_root: &Path,
+                  full_check: bool,
+                  defaults: &FileAttributes)
+                  -> Result<Vec<PathStatus>, Error> {
+        let mut result = Vec::new();
+        let mut path = PathBuf::new();
+        let attributes = Attributes::load(repo_root, defaults)?;
+        self.collect_status(branch, repo_root, &attributes, full_check, &ROOT_INODE, &mut path,
+                            &mut result)?;
+        for file in self.untracked_files(repo_root)? {
+            result.push(PathStatus {
+                path: file,
+                kind: StatusKind::Untracked,
+            })
+        }
+        result.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(result)
+    }
+
+    fn collect_status(&self,
+                      branch: &Branch,
+                      repo_root: &Path,
+                      attributes: &Attributes,
+                      full_check: bool,
+                      current_inode: &Inode,
+                      path: &mut PathBuf,
+                      result: &mut Vec<PathStatus>)
+                      -> Result<(), Error> {
+        let file_id = OwnedFileId {
+            parent_inode: current_inode.clone(),
+            basename: SmallString::from_str(""),
+        };
+        for (k, v) in self.iter_tree(Some((&file_id.as_file_id(), None)))
+            .take_while(|&(ref k, _)| k.parent_inode == current_inode) {
+                if k.basename.len() == 0 {
+                    continue
+                }
+                path.push(k.basename.as_str());
+                let realpath = repo_root.join(&path);
+                let mut kinds = Vec::new();
+                match self.inode_status(v, &realpath) {
+                    None => kinds.push(StatusKind::Added),
+                    Some((WorkingFileStatus::Deleted, header)) => {
+                        if header.status == FileStatus::Deleted {
+                            kinds.push(StatusKind::Deleted)
+                        } else {
+                            kinds.push(StatusKind::Missing)
+                        }
+                    }
+                    Some((WorkingFileStatus::Moved { from, to }, header)) => {
+                        match self.node_path(branch, &header.key) {
+                            Some(ref old_path) if *old_path != *path => {
+                                kinds.push(StatusKind::Moved { from: old_path.clone() })
+                            }
+                            _ => {}
+                        }
+                        if from != to ||
+                            (!to.is_dir() &&
+                             self.is_modified(branch, attributes, &header.key, to, &realpath)?) {
+                            kinds.push(StatusKind::Modified)
+                        }
+                    }
+                    Some((WorkingFileStatus::Ok, header)) => {
+                        if !header.metadata.is_dir() &&
+                            (full_check || !self.has_valid_stamp(branch, v, &realpath)) &&
+                            self.is_modified(branch, attributes, &header.key, header.metadata,
+                                             &realpath)? {
+                            kinds.push(StatusKind::Modified)
+                        }
+                    }
+                }
+                for kind in kinds {
+                    result.push(PathStatus { path: path.clone(), kind: kind })
+                }
+                self.collect_status(branch, repo_root, attributes, full_check, v, path, result)?;
+                path.pop();
+            }
+        Ok(())
+    }
+
+    /// Tells whether the contents of file `realpath`, with metadata
+    /// `meta`, differ from those of node `key` in `branch`.
+    fn is_modified(&self,
+                   branch: &Branch,
+                   attributes: &Attributes,
+                   key: &Key<PatchId>,
+                   meta: FileMetadata,
+                   realpath: &Path)
+                   -> Result<bool, Error> {
+        let attributes = attributes.get_real(realpath);
+        let mut recorded = Vec::new();
+        {
+            let mut graph = self.retrieve(branch, key);
+            self.output_file_contents(&mut graph, &attributes, &mut recorded, &mut Vec::new())?;
+        }
+        let mut current = Vec::new();
+        read_contents(realpath, meta, &mut current)?;
+        attributes.normalize_eol(&mut current);
+        Ok(recorded != current)
+    }
+}
+
+impl<A: Transaction, R: rand::Rng> T<A, R> {
+    /// Compares `branch` with the working copy, collecting the
+    /// changes and the stamps of the files found unchanged.
+    fn record_state(&self,
+                    branch: &Branch,
+                    working_copy: &std::path::Path,
+                    prefixes: &[PathBuf],
+                    full_check: bool,
+                    defaults: &FileAttributes)
+                    -> Result<RecordState, Error> {
         let mut st = RecordState {
             line_num: LineId::new() + 1,
             actions: Vec::new(),
             updatables: Vec::new(),
             redundant: Vec::new(),
+            repo_root: working_copy.to_path_buf(),
+            attributes: Attributes::load(working_copy, defaults)?,
+            prefixes: prefixes.to_vec(),
+            full_check: full_check,
+            stamps: Vec::new(),
         };
-        {
-            let mut realpath = PathBuf::from(working_copy);
-            self.record_root(&branch, &mut st, &mut realpath)?;
-            debug!("record done, {} changes", st.actions.len());
-            debug!("changes: {:?}", st.actions);
-        }
+        let mut realpath = PathBuf::from(working_copy);
+        self.record_root(branch, &mut st, &mut realpath)?;
+        debug!("record done, {} changes", st.actions.len());
+        debug!("changes: {:?}", st.actions);
+        Ok(st)
+    }
+}
+
+impl<'env, T: rand::Rng> MutTxn<'env, T> {
+    /// Computes the changes to record in the working copy. If
+    /// `prefixes` is non-empty, only the files under these paths
+    /// (relative to `working_copy`) are examined.
+    ///
+    /// Files whose stamp has not changed since they were last found
+    /// unchanged are not read, unless `full_check` is true. Files
+    /// are read according to their attributes, `defaults` applying
+    /// where the attributes file does not say otherwise.
+    pub fn record(&mut self,
+                  branch_name: &str,
+                  working_copy: &std::path::Path,
+                  prefixes: &[PathBuf],
+                  full_check: bool,
+                  defaults: &FileAttributes)
+                  -> Result<(Vec<Record>, Vec<InodeUpdate>), Error> {
+
+        let branch = try!(self.open_branch(branch_name));
+        let st = self.record_state(&branch, working_copy, prefixes, full_check, defaults)?;
+        self.save_stamps(&st.stamps)?;
         // try!(self.remove_redundant_edges(&mut branch, &mut st.redundant));
         try!(self.commit_branch(branch));
         debug!("remove_redundant_edges done");
         Ok((st.actions, st.updatables))
     }
+
+    /// Same as `record`, on an existing branch, which is left
+    /// untouched. The only change made to the repository is saving
+    /// the stamps of the files found unchanged.
+    pub fn diff_working_copy(&mut self,
+                             branch: &Branch,
+                             working_copy: &std::path::Path,
+                             prefixes: &[PathBuf],
+                             full_check: bool,
+                             defaults: &FileAttributes)
+                             -> Result<(Vec<Record>, Vec<InodeUpdate>), Error> {
+        let st = self.record_state(branch, working_copy, prefixes, full_check, defaults)?;
+        self.save_stamps(&st.stamps)?;
+        Ok((st.actions, st.updatables))
+    }
+
+    fn save_stamps(&mut self, stamps: &[(Inode, FileStamp)]) -> Result<(), Error> {
+        for &(ref inode, ref stamp) in stamps.iter() {
+            self.replace_stamps(inode, stamp)?;
+        }
+        Ok(())
+    }
 }
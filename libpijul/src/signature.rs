@@ -0,0 +1,155 @@
+//! Detached Ed25519 signatures of patches.
+//!
+//! A signature is computed over the hash of a patch, and is stored in
+//! the comment field of the gzip header of the patch file. It is
+//! therefore transferred along with the patch, without being part of
+//! the hashed contents of the patch itself.
+
+use std::path::Path;
+use std::fs::{File, rename};
+use std::io::{BufReader, Read, Write};
+use rustc_serialize::base64::{FromBase64, ToBase64, URL_SAFE};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, ED25519, verify};
+use untrusted::Input;
+use flate2;
+use backend::HashRef;
+use fs_representation::{patches_dir, patch_file_name};
+use error::Error;
+
+const SIGNATURE_PREFIX: &'static str = "ed25519";
+
+/// An Ed25519 key pair, used to sign patches.
+pub struct SigningKey {
+    key_pair: Ed25519KeyPair,
+}
+
+impl SigningKey {
+    /// Generates a new key pair, returning it along with its PKCS#8
+    /// encoding, which is the format used to store it.
+    pub fn generate() -> Result<(SigningKey, Vec<u8>), Error> {
+        let rng = SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).map_err(|_| Error::InvalidKey)?;
+        let key = SigningKey::from_pkcs8(&pkcs8[..])?;
+        Ok((key, pkcs8.to_vec()))
+    }
+
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Result<SigningKey, Error> {
+        let key_pair = Ed25519KeyPair::from_pkcs8(Input::from(pkcs8)).map_err(|_| Error::InvalidKey)?;
+        Ok(SigningKey { key_pair: key_pair })
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(self.key_pair.public_key_bytes().to_vec())
+    }
+
+    /// Signs the patch with hash `hash`.
+    pub fn sign(&self, hash: HashRef) -> PatchSignature {
+        let signature = self.key_pair.sign(hash.to_base64(URL_SAFE).as_bytes());
+        PatchSignature {
+            public_key: self.public_key(),
+            signature: signature.as_ref().to_vec(),
+        }
+    }
+}
+
+/// The public part of a `SigningKey`, written in base64 when shown to
+/// users.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PublicKey(Vec<u8>);
+
+impl PublicKey {
+    pub fn from_base64(s: &str) -> Option<PublicKey> {
+        match s.from_base64() {
+            Ok(ref v) if v.len() == 32 => Some(PublicKey(v.clone())),
+            _ => None,
+        }
+    }
+
+    pub fn to_base64(&self) -> String {
+        self.0.to_base64(URL_SAFE)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchSignature {
+    pub public_key: PublicKey,
+    pub signature: Vec<u8>,
+}
+
+impl PatchSignature {
+    /// Tells whether this is a valid signature of the patch with hash
+    /// `hash`.
+    pub fn verify(&self, hash: HashRef) -> bool {
+        verify(&ED25519,
+               Input::from(&self.public_key.0[..]),
+               Input::from(hash.to_base64(URL_SAFE).as_bytes()),
+               Input::from(&self.signature[..]))
+            .is_ok()
+    }
+
+    fn to_comment(&self) -> String {
+        format!("{} {} {}",
+                SIGNATURE_PREFIX,
+                self.public_key.to_base64(),
+                self.signature.to_base64(URL_SAFE))
+    }
+
+    fn from_comment(comment: &[u8]) -> Option<PatchSignature> {
+        let comment = match ::std::str::from_utf8(comment) {
+            Ok(comment) => comment,
+            Err(_) => return None,
+        };
+        let mut words = comment.split(' ');
+        match (words.next(), words.next(), words.next(), words.next()) {
+            (Some(SIGNATURE_PREFIX), Some(public_key), Some(signature), None) => {
+                match (PublicKey::from_base64(public_key), signature.from_base64()) {
+                    (Some(public_key), Ok(signature)) => {
+                        Some(PatchSignature {
+                            public_key: public_key,
+                            signature: signature,
+                        })
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Reads the signature of patch `hash` in repository `repo_root`,
+/// if the patch is signed.
+pub fn read_signature(repo_root: &Path, hash: HashRef) -> Result<Option<PatchSignature>, Error> {
+    let path = patches_dir(repo_root).join(&patch_file_name(hash));
+    let f = BufReader::new(File::open(&path)?);
+    let decoder = flate2::bufread::GzDecoder::new(f)?;
+    Ok(decoder.header().comment().and_then(PatchSignature::from_comment))
+}
+
+/// Signs patch `hash` of repository `repo_root` with `key`, replacing
+/// any previous signature.
+pub fn sign_patch(repo_root: &Path, hash: HashRef, key: &SigningKey) -> Result<PatchSignature, Error> {
+    let signature = key.sign(hash);
+    let path = patches_dir(repo_root).join(&patch_file_name(hash));
+    let mut contents = Vec::new();
+    let filename = {
+        let f = BufReader::new(File::open(&path)?);
+        let mut decoder = flate2::bufread::GzDecoder::new(f)?;
+        decoder.read_to_end(&mut contents)?;
+        decoder.header().filename().map(|f| f.to_vec()).unwrap_or(Vec::new())
+    };
+    let mut tmp_path = path.clone();
+    tmp_path.set_extension("gz.tmp");
+    {
+        let f = File::create(&tmp_path)?;
+        let mut w = flate2::GzBuilder::new()
+            .filename(&filename)
+            .comment(signature.to_comment().as_bytes())
+            .write(f, flate2::Compression::Best);
+        w.write_all(&contents)?;
+        w.finish()?;
+    }
+    rename(&tmp_path, &path)?;
+    Ok(signature)
+}
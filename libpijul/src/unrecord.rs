@@ -607,6 +607,7 @@ impl<'env, T: rand::Rng> MutTxn<'env, T> {
 
             debug!("unrecord: {:?} {:?}", patch_id, patch);
             self.unapply(branch, patch_id, patch)?;
+            self.clear_stamps()?;
 
             let timestamp = self.get_patch(&branch.patches, patch_id).unwrap();
 
@@ -2,11 +2,13 @@ use clap::{SubCommand, ArgMatches, Arg};
 use commands::{StaticSubcommand, default_explain};
 use libpijul::{Hash, apply_resize};
 use libpijul::patch::Patch;
+use libpijul::fs_representation::patches_dir;
 
 use error::Error;
+use meta::{Meta, KeyStore};
 use std::collections::HashSet;
 
-use std::io::{BufReader, stdin};
+use std::io::{BufRead, BufReader, stdin};
 use super::BasicOptions;
 
 pub fn invocation() -> StaticSubcommand {
@@ -32,23 +34,32 @@ pub fn invocation() -> StaticSubcommand {
 pub fn run(args: &ArgMatches) -> Result<(), Error> {
     let opts = BasicOptions::from_args(args)?;
     debug!("applying");
-    let remote: HashSet<Hash> = if let Some(hashes) = args.values_of("hash") {
-        hashes.map(|h| Hash::from_base64(&h).unwrap()).collect()
+    let remote: HashSet<Hash> = if let Some(hashes) = args.values_of("patch") {
+        let mut remote = HashSet::new();
+        for h in hashes {
+            remote.insert(Hash::from_base64(&h).ok_or(Error::WrongHash)?);
+        }
+        remote
     } else {
-        // Read patches in gz format from stdin.
+        // Read patches in gz format from stdin, one after the other,
+        // and save them in the repository.
         let mut hashes = HashSet::new();
         let mut buffered = BufReader::new(stdin());
-        while let Ok((h, _, patch)) = Patch::from_reader_compressed(&mut buffered) {
+        while !buffered.fill_buf()?.is_empty() {
+            let (h, _, patch) = Patch::from_reader_compressed(&mut buffered)?;
             debug!("{:?}", patch);
+            patch.save(patches_dir(&opts.repo_root))?;
             hashes.insert(h);
-            break;
         }
         hashes
     };
 
     debug!("remote={:?}", remote);
+    let meta = Meta::load(&opts.repo_root);
+    KeyStore::load()?.check_signatures(&opts.repo_root, remote.iter(), meta.signature_policy)?;
+    let defaults = meta.file_attributes();
     loop {
-        match apply_resize(&opts.repo_root, &opts.branch(), remote.iter()) {
+        match apply_resize(&opts.repo_root, &opts.branch(), remote.iter(), &defaults) {
             Err(ref e) if e.lacks_space() => {}
             Ok(()) => return Ok(()),
             Err(e) => return Err(From::from(e)),
@@ -6,7 +6,7 @@ use libpijul::patch::{Change, Patch, Record, EdgeOp, PatchHeader};
 use std::io::stdout;
 use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use error::Error;
 use libpijul::{MutTxn, LineId, FOLDER_EDGE, PARENT_EDGE, DELETED_EDGE, Hash};
@@ -14,6 +14,7 @@ use std::char::from_u32;
 use std::str;
 use std;
 use rand;
+use rpassword;
 use rustyline;
 use term::{StdoutTerminal, Attr};
 use term;
@@ -496,6 +497,31 @@ pub fn ask_patch_name() -> Result<String, Error> {
     Ok(input)
 }
 
+/// Asks which side of a conflict with `n_sides` sides to keep,
+/// returning its index (from 0), or `None` to leave the conflict
+/// unresolved.
+pub fn ask_conflict_side(n_sides: usize) -> Result<Option<usize>, Error> {
+    let mut rl = rustyline::Editor::<()>::new();
+    loop {
+        try!(std::io::stdout().flush());
+        let input = rl.readline(&format!("Keep which side? [1-{}, s to skip] ", n_sides))?;
+        let input = input.trim();
+        if input == "s" || input == "S" {
+            return Ok(None)
+        }
+        match input.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= n_sides => return Ok(Some(n - 1)),
+            _ => println!("Please answer a number between 1 and {}, or s.", n_sides),
+        }
+    }
+}
+
+/// Asks the passphrase of the encrypted private key at `path`,
+/// without echoing it.
+pub fn ask_passphrase(path: &Path) -> Result<String, Error> {
+    Ok(rpassword::prompt_password_stdout(&format!("Passphrase for {}: ", path.display()))?)
+}
+
 pub fn ask_learn_ssh(host: &str, port: u16, fingerprint: &str) -> Result<bool, Error> {
     try!(std::io::stdout().flush());
     print!("The authenticity of host {:?}:{} cannot be established.\nThe fingerprint is {:?}.",
@@ -1,12 +1,15 @@
 use clap::{SubCommand, ArgMatches, Arg};
 use commands::{BasicOptions, StaticSubcommand, default_explain};
-use libpijul::{PatchId, Key, Value, Txn};
+use libpijul::{Hash, PatchId, Key, Value, Txn};
 use libpijul::graph::LineBuffer;
 use libpijul::fs_representation::read_patch_nochanges;
+use libpijul::patch::PatchHeader;
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
 use error;
 use std::fs::{canonicalize};
-use std::io::{stdout, Stdout};
+use std::io::{stdout, Write};
 
 pub fn invocation() -> StaticSubcommand {
     return SubCommand::with_name("blame")
@@ -20,6 +23,22 @@ pub fn invocation() -> StaticSubcommand {
             .long("branch")
             .help("The branch to get the history from. Defaults to the current branch.")
             .takes_value(true))
+        .arg(Arg::with_name("lines")
+            .short("L")
+            .long("lines")
+            .help("Only annotate lines START to END (starting from 1), given as START,END, \
+                   or START, (until the end of the file), or a single line.")
+            .takes_value(true))
+        .arg(Arg::with_name("porcelain")
+            .long("porcelain")
+            .help("Output in a stable format meant for other programs: for each line, a \
+                   line with the patch hash and the line number, then one line per field \
+                   of the patch, and the contents of the line prefixed by a tab. Conflict \
+                   markers are not listed."))
+        .arg(Arg::with_name("json")
+            .long("json")
+            .help("Output in JSON. Conflict markers are not listed.")
+            .conflicts_with("porcelain"))
         .arg(Arg::with_name("file")
             .help("File to annotate.")
             .required(true)
@@ -27,9 +46,139 @@ pub fn invocation() -> StaticSubcommand {
         );
 }
 
+/// A line of the file, as output in the working copy.
+enum BlameLine {
+    Line {
+        key: Key<PatchId>,
+        contents: Vec<u8>,
+        /// In a conflict, the number of the conflict in the file and
+        /// the side of the conflict this line is on (both starting
+        /// from 0).
+        side: Option<(usize, usize)>,
+    },
+    Marker(&'static str),
+}
+
+/// A `LineBuffer` collecting the lines of a file along with their
+/// position in conflicts.
+struct BlameBuffer {
+    lines: Vec<BlameLine>,
+    side: Option<(usize, usize)>,
+    n_conflicts: usize,
+}
+
+impl<'a, T: 'a + Transaction> LineBuffer<'a, T> for BlameBuffer {
+
+    fn output_line(&mut self, key: &Key<PatchId>, contents: Value<'a, T>) -> Result<(), libpijul::error::Error> {
+        let mut line = Vec::new();
+        for chunk in contents {
+            line.extend(chunk)
+        }
+        // The file node itself has no contents.
+        if !line.is_empty() {
+            self.lines.push(BlameLine::Line {
+                key: key.clone(),
+                contents: line,
+                side: self.side,
+            })
+        }
+        Ok(())
+    }
+
+    fn output_conflict_marker(&mut self, _: &'a str) -> Result<(), libpijul::error::Error> {
+        Ok(())
+    }
+
+    fn begin_conflict(&mut self) -> Result<(), libpijul::error::Error> {
+        self.side = Some((self.n_conflicts, 0));
+        self.n_conflicts += 1;
+        self.lines.push(BlameLine::Marker(START_MARKER));
+        Ok(())
+    }
+
+    fn conflict_next(&mut self) -> Result<(), libpijul::error::Error> {
+        if let Some((_, ref mut side)) = self.side {
+            *side += 1
+        }
+        self.lines.push(BlameLine::Marker(SEPARATOR));
+        Ok(())
+    }
+
+    fn end_conflict(&mut self) -> Result<(), libpijul::error::Error> {
+        self.side = None;
+        self.lines.push(BlameLine::Marker(END_MARKER));
+        Ok(())
+    }
+}
+
+/// Patch headers, read from disk the first time a patch is seen.
+struct Headers<'a> {
+    txn: &'a Txn<'a>,
+    repo_root: &'a Path,
+    cache: HashMap<PatchId, (Hash, PatchHeader)>,
+}
+
+impl<'a> Headers<'a> {
+    fn get(&mut self, patch: PatchId) -> Result<&(Hash, PatchHeader), error::Error> {
+        if !self.cache.contains_key(&patch) {
+            let ext = self.txn.get_external(&patch).unwrap();
+            let header = read_patch_nochanges(self.repo_root, ext)?;
+            self.cache.insert(patch, (ext.to_owned(), header));
+        }
+        Ok(self.cache.get(&patch).unwrap())
+    }
+}
+
+/// Parses a line range given to `-L`, as a first line and an
+/// optional last line, both included.
+fn parse_range(s: &str) -> Result<(usize, Option<usize>), error::Error> {
+    let err = || error::Error::InvalidLineRange(s.to_string());
+    let mut it = s.splitn(2, ',');
+    let start = it.next().unwrap().trim().parse::<usize>().map_err(|_| err())?;
+    let end = match it.next().map(|e| e.trim()) {
+        None => Some(start),
+        Some("") => None,
+        Some(e) => Some(e.parse::<usize>().map_err(|_| err())?),
+    };
+    if start == 0 || end.map(|e| e < start).unwrap_or(false) {
+        return Err(err())
+    }
+    Ok((start, end))
+}
+
+fn write_contents<W: Write>(w: &mut W, contents: &[u8]) -> Result<(), error::Error> {
+    w.write_all(contents)?;
+    if contents.last() != Some(&b'\n') {
+        writeln!(w, "")?
+    }
+    Ok(())
+}
+
+fn line_to_json(n: usize, hash: &Hash, header: &PatchHeader, contents: &[u8],
+                side: Option<(usize, usize)>) -> Json {
+    let mut l = BTreeMap::new();
+    l.insert("line".to_string(), n.to_json());
+    l.insert("hash".to_string(), hash.to_base64(URL_SAFE).to_json());
+    l.insert("name".to_string(), header.name.to_json());
+    l.insert("authors".to_string(), header.authors.to_json());
+    l.insert("timestamp".to_string(), header.timestamp.to_rfc3339().to_json());
+    l.insert("contents".to_string(), String::from_utf8_lossy(contents).to_json());
+    if let Some((conflict, side)) = side {
+        let mut c = BTreeMap::new();
+        c.insert("number".to_string(), (conflict + 1).to_json());
+        c.insert("side".to_string(), (side + 1).to_json());
+        l.insert("conflict".to_string(), Json::Object(c));
+    }
+    Json::Object(l)
+}
+
 pub fn run(args: &ArgMatches) -> Result<(), error::Error> {
     let opts = BasicOptions::from_args(args)?;
     let file = Path::new(args.value_of("file").unwrap());
+    let (start, end) = match args.value_of("lines") {
+        Some(range) => parse_range(range)?,
+        None => (1, None),
+    };
     let p = canonicalize(opts.cwd.join(file))?;
     if let Ok(file) = p.strip_prefix(&opts.repo_root) {
         let repo = opts.open_repo()?;
@@ -41,49 +190,79 @@ pub fn run(args: &ArgMatches) -> Result<(), error::Error> {
             }
             let node = txn.get_inodes(&inode).unwrap();
             let mut graph = txn.retrieve(&branch, &node.key);
-            let mut buf = OutBuffer {
-                stdout: stdout(),
+            let mut buf = BlameBuffer {
+                lines: Vec::new(),
+                side: None,
+                n_conflicts: 0,
+            };
+            txn.output_file(&mut buf, &mut graph, &mut Vec::new())?;
+
+            let mut headers = Headers {
                 txn: &txn,
-                target: &opts.repo_root,
+                repo_root: &opts.repo_root,
+                cache: HashMap::new(),
             };
-            txn.output_file(&mut buf, &mut graph, &mut Vec::new())?
+            let width = format!("{}", buf.lines.len()).len();
+            let stdout = stdout();
+            let mut stdout = stdout.lock();
+            let mut json = Vec::new();
+            for (i, line) in buf.lines.iter().enumerate() {
+                let n = i + 1;
+                if n < start || end.map(|e| n > e).unwrap_or(false) {
+                    continue
+                }
+                let (key, contents, side) = match *line {
+                    BlameLine::Line { ref key, ref contents, side } => (key, contents, side),
+                    BlameLine::Marker(marker) => {
+                        if !args.is_present("porcelain") && !args.is_present("json") {
+                            write!(stdout, "{:>width$}: {}", n, marker, width = width)?
+                        }
+                        continue
+                    }
+                };
+                let patch = key.patch;
+                let &(ref hash, ref header) = headers.get(patch)?;
+                if args.is_present("json") {
+                    json.push(line_to_json(n, hash, header, contents, side))
+                } else if args.is_present("porcelain") {
+                    writeln!(stdout, "{} {}", hash.to_base64(URL_SAFE), n)?;
+                    for author in header.authors.iter() {
+                        writeln!(stdout, "author {}", author)?
+                    }
+                    writeln!(stdout, "timestamp {}", header.timestamp.to_rfc3339())?;
+                    writeln!(stdout, "name {}", header.name)?;
+                    if let Some((conflict, side)) = side {
+                        writeln!(stdout, "conflict {} side {}", conflict + 1, side + 1)?
+                    }
+                    write!(stdout, "\t")?;
+                    write_contents(&mut stdout, contents)?
+                } else {
+                    write!(stdout, "{:>width$}: {} {} {}",
+                           n,
+                           header.authors.get(0).map(|a| a.as_str()).unwrap_or(""),
+                           header.timestamp.format("%F %R %Z"),
+                           patch.to_base64(URL_SAFE),
+                           width = width)?;
+                    if let Some((_, side)) = side {
+                        write!(stdout, " (side {})", side + 1)?
+                    }
+                    write!(stdout, " > ")?;
+                    write_contents(&mut stdout, contents)?
+                }
+            }
+            if args.is_present("json") {
+                writeln!(stdout, "{}", Json::Array(json).pretty())?
+            }
         }
     }
     Ok(())
 }
 
-struct OutBuffer<'a> { stdout: Stdout, txn: &'a Txn<'a>, target: &'a Path }
-
 use libpijul::Transaction;
 use libpijul;
-use std::io::Write;
+use libpijul::conflict::{START_MARKER, SEPARATOR, END_MARKER};
 use rustc_serialize::base64::{URL_SAFE, ToBase64};
 
-impl<'a, T: 'a + Transaction> LineBuffer<'a, T> for OutBuffer<'a> {
-
-    fn output_line(&mut self, key: &Key<PatchId>, contents: Value<'a, T>) -> Result<(), libpijul::error::Error> {
-        let ext = self.txn.get_external(&key.patch).unwrap();
-        let patch = read_patch_nochanges(self.target, ext)?;
-        write!(self.stdout, "{} {} {} > ", patch.authors[0], patch.timestamp.format("%F %R %Z"), key.patch.to_base64(URL_SAFE))?;
-        let mut ends_with_eol = false;
-        for chunk in contents {
-            self.stdout.write_all(chunk)?;
-            if let Some(&c) = chunk.last() {
-                ends_with_eol = c == b'\n'
-            }
-        }
-        if !ends_with_eol {
-            writeln!(self.stdout, "")?;
-        }
-        Ok(())
-    }
-
-    fn output_conflict_marker(&mut self, s: &'a str) -> Result<(), libpijul::error::Error> {
-        write!(self.stdout, "{}", s)?;
-        Ok(())
-    }
-}
-
 
 pub fn explain(res: Result<(), error::Error>) {
     default_explain(res)
@@ -1,10 +1,17 @@
 use clap::{SubCommand, ArgMatches, Arg};
+use chrono::{DateTime, NaiveDate, UTC, TimeZone};
 use commands::{BasicOptions, StaticSubcommand, ask, default_explain};
-use libpijul::fs_representation::{read_patch_nochanges, id_file};
+use commands::remote::write_changes_after;
+use libpijul::{ApplyTimestamp, Branch, Hash, Key, PatchId, Txn, ROOT_KEY};
+use libpijul::fs_representation::{read_patch, read_patch_nochanges, id_file};
+use libpijul::patch::PatchHeader;
+use regex::Regex;
 use rustc_serialize::base64::{ToBase64, URL_SAFE};
+use rustc_serialize::json::{Json, ToJson};
 use error::Error;
-use std::fs::File;
-use std::io::Read;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{File, canonicalize};
+use std::io::{Read, stdout};
 use std::str;
 
 pub fn invocation() -> StaticSubcommand {
@@ -22,6 +29,256 @@ pub fn invocation() -> StaticSubcommand {
         .arg(Arg::with_name("hash-only")
             .long("hash-only")
             .help("Only display the hash of each path."))
+        .arg(Arg::with_name("applied-after")
+            .long("applied-after")
+            .help("With --hash-only, only list the patches applied after this apply \
+                   timestamp, preceded by the number of patches on the branch.")
+            .takes_value(true)
+            .requires("hash-only")
+            .validator(|val| {
+                let x: Result<ApplyTimestamp, _> = val.parse();
+                match x {
+                    Ok(_) => Ok(()),
+                    Err(_) => Err(val),
+                }
+            }))
+        .arg(Arg::with_name("author")
+            .long("author")
+            .help("Only list patches with an author containing this string.")
+            .takes_value(true)
+            .conflicts_with("hash-only"))
+        .arg(Arg::with_name("since")
+            .long("since")
+            .help("Only list patches recorded at or after this date (YYYY-MM-DD or RFC 3339).")
+            .takes_value(true)
+            .conflicts_with("hash-only"))
+        .arg(Arg::with_name("until")
+            .long("until")
+            .help("Only list patches recorded at or before this date (YYYY-MM-DD or RFC 3339).")
+            .takes_value(true)
+            .conflicts_with("hash-only"))
+        .arg(Arg::with_name("name")
+            .long("name")
+            .help("Only list patches whose name matches this regular expression.")
+            .takes_value(true)
+            .conflicts_with("hash-only"))
+        .arg(Arg::with_name("path")
+            .long("path")
+            .help("Only list patches touching this file or directory \
+                   (multiple occurrences allowed).")
+            .takes_value(true)
+            .multiple(true)
+            .conflicts_with("hash-only"))
+        .arg(Arg::with_name("limit")
+            .short("n")
+            .long("limit")
+            .help("List at most this number of patches.")
+            .takes_value(true)
+            .conflicts_with("hash-only"))
+        .arg(Arg::with_name("format")
+            .long("format")
+            .help("Output format.")
+            .takes_value(true)
+            .possible_values(&["full", "one-line", "json"])
+            .conflicts_with("hash-only"))
+        .arg(Arg::with_name("template")
+            .long("template")
+            .help("Print each patch according to this template, in which {hash}, {name}, \
+                   {authors}, {description}, {timestamp} and {date} are replaced by the \
+                   corresponding field of the patch.")
+            .takes_value(true)
+            .conflicts_with("hash-only")
+            .conflicts_with("format"))
+}
+
+/// Parses a date given either as a day (YYYY-MM-DD), or as a full
+/// RFC 3339 date. Days start at midnight UTC, or end at the last
+/// second of the day if `end_of_day` is true.
+fn parse_date(s: &str, end_of_day: bool) -> Result<DateTime<UTC>, Error> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(s) {
+        return Ok(date.with_timezone(&UTC))
+    }
+    match NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        Ok(day) => {
+            let time = if end_of_day {
+                day.and_hms(23, 59, 59)
+            } else {
+                day.and_hms(0, 0, 0)
+            };
+            Ok(UTC.from_utc_datetime(&time))
+        }
+        Err(_) => Err(Error::InvalidDate(s.to_string())),
+    }
+}
+
+/// Criteria selecting the patches listed by `changes` and `log`.
+pub struct Filters {
+    author: Option<String>,
+    since: Option<DateTime<UTC>>,
+    until: Option<DateTime<UTC>>,
+    name: Option<Regex>,
+    /// For each path, the set of nodes that are or have been in that
+    /// file or directory.
+    paths: Vec<HashSet<Key<PatchId>>>,
+}
+
+impl Filters {
+    /// The filters given by the `author`, `since`, `until`, `name`
+    /// and `path` arguments, those that are absent matching all
+    /// patches.
+    pub fn from_args(txn: &Txn, opts: &BasicOptions, branch: &Branch, args: &ArgMatches)
+                     -> Result<Filters, Error> {
+        let mut paths = Vec::new();
+        if let Some(p) = args.values_of("path") {
+            for path in p {
+                paths.push(path_keys(txn, opts, branch, path)?)
+            }
+        }
+        Ok(Filters {
+            author: args.value_of("author").map(|a| a.to_string()),
+            since: match args.value_of("since") {
+                Some(d) => Some(parse_date(d, false)?),
+                None => None,
+            },
+            until: match args.value_of("until") {
+                Some(d) => Some(parse_date(d, true)?),
+                None => None,
+            },
+            name: match args.value_of("name") {
+                Some(r) => Some(Regex::new(r)?),
+                None => None,
+            },
+            paths: paths,
+        })
+    }
+
+    fn matches_header(&self, patch: &PatchHeader) -> bool {
+        if let Some(ref author) = self.author {
+            if !patch.authors.iter().any(|a| a.contains(author.as_str())) {
+                return false
+            }
+        }
+        if let Some(ref since) = self.since {
+            if patch.timestamp < *since {
+                return false
+            }
+        }
+        if let Some(ref until) = self.until {
+            if patch.timestamp > *until {
+                return false
+            }
+        }
+        if let Some(ref name) = self.name {
+            if !name.is_match(&patch.name) {
+                return false
+            }
+        }
+        true
+    }
+}
+
+/// How `changes` and `log` print each patch.
+#[derive(Clone, Copy)]
+pub enum Format<'a> {
+    Full,
+    OneLine,
+    Json,
+    Template(&'a str),
+}
+
+impl<'a> Format<'a> {
+    /// The format given by the `template` or `format` argument.
+    pub fn from_args(args: &'a ArgMatches) -> Format<'a> {
+        if let Some(template) = args.value_of("template") {
+            Format::Template(template)
+        } else {
+            match args.value_of("format") {
+                Some("one-line") => Format::OneLine,
+                Some("json") => Format::Json,
+                _ => Format::Full,
+            }
+        }
+    }
+}
+
+/// Parses the `limit` argument.
+pub fn parse_limit(args: &ArgMatches) -> Result<Option<usize>, Error> {
+    match args.value_of("limit") {
+        Some(n) => Ok(Some(n.parse::<usize>().map_err(|_| Error::InvalidLimit(n.to_string()))?)),
+        None => Ok(None),
+    }
+}
+
+/// Fills `template` in a single pass, so that placeholders appearing
+/// in the patch fields themselves are left as they are.
+fn fill_template(template: &str, hash: &Hash, patch: &PatchHeader) -> String {
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some(i) = rest.find(|c: char| c == '{' || c == '\\') {
+        result.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with("\\n") {
+            result.push('\n');
+            rest = &rest[2..];
+            continue
+        } else if rest.starts_with("\\t") {
+            result.push('\t');
+            rest = &rest[2..];
+            continue
+        } else if rest.starts_with('{') {
+            if let Some(j) = rest.find('}') {
+                let value = match &rest[1..j] {
+                    "hash" => Some(hash.to_base64(URL_SAFE)),
+                    "name" => Some(patch.name.clone()),
+                    "authors" => Some(patch.authors.join(", ")),
+                    "description" => Some(patch.description.clone().unwrap_or(String::new())),
+                    "timestamp" => Some(patch.timestamp.to_rfc3339()),
+                    "date" => Some(patch.timestamp.format("%F").to_string()),
+                    _ => None,
+                };
+                if let Some(value) = value {
+                    result.push_str(&value);
+                    rest = &rest[j + 1..];
+                    continue
+                }
+            }
+        }
+        result.push_str(&rest[..1]);
+        rest = &rest[1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// The header of a patch as a JSON object.
+fn patch_to_json(hash: &Hash, patch: &PatchHeader) -> Json {
+    let mut p = BTreeMap::new();
+    p.insert("hash".to_string(), hash.to_base64(URL_SAFE).to_json());
+    p.insert("name".to_string(), patch.name.to_json());
+    p.insert("authors".to_string(), patch.authors.to_json());
+    p.insert("description".to_string(), patch.description.to_json());
+    p.insert("timestamp".to_string(), patch.timestamp.to_rfc3339().to_json());
+    Json::Object(p)
+}
+
+/// The nodes that are or have been part of file `path`, given
+/// relative to the current directory.
+fn path_keys(txn: &Txn, opts: &BasicOptions, branch: &Branch, path: &str)
+             -> Result<HashSet<Key<PatchId>>, Error> {
+    let p = canonicalize(opts.cwd.join(path))?;
+    let file = match p.strip_prefix(&opts.repo_root) {
+        Ok(file) => file.to_path_buf(),
+        Err(_) => return Err(Error::InvalidPath(path.to_string())),
+    };
+    if file.as_os_str().is_empty() {
+        return Ok(txn.file_history_keys(branch, &ROOT_KEY))
+    }
+    let inode = txn.find_inode(&file)?;
+    let key = match txn.get_inodes(&inode) {
+        Some(header) => header.key.clone(),
+        None => return Ok(HashSet::new()),
+    };
+    Ok(txn.file_history_keys(branch, &key))
 }
 
 pub fn run(args: &ArgMatches) -> Result<(), Error> {
@@ -39,18 +296,71 @@ pub fn run(args: &ArgMatches) -> Result<(), Error> {
     let repo = opts.open_repo()?;
     let txn = try!(repo.txn_begin());
     if let Some(branch) = txn.get_branch(&opts.branch()) {
-        if hash_only {
+        if let Some(after) = args.value_of("applied-after") {
+            let stdout = stdout();
+            write_changes_after(&mut stdout.lock(), &txn, &branch, after.parse().unwrap())?
+        } else if hash_only {
             for (s, hash) in txn.iter_applied(&branch, None) {
                 let hash_ext = txn.get_external(&hash).unwrap();
                 println!("{}:{}", hash_ext.to_base64(URL_SAFE), s)
             }
         } else {
-            for (_, hash) in txn.rev_iter_applied(&branch, None) {
-                let hash_ext = txn.get_external(&hash).unwrap();
-                let patch = read_patch_nochanges(&opts.repo_root, hash_ext)?;
-                ask::print_patch_descr(&hash_ext.to_owned(), &patch)
+            let filters = Filters::from_args(&txn, &opts, &branch, args)?;
+            print_changes(&txn, &opts, &branch, &filters, parse_limit(args)?,
+                          Format::from_args(args))?
+        }
+    }
+    Ok(())
+}
+
+/// Prints the patches of `branch` matching `filters`, most recent
+/// first, stopping after `limit` patches if it is given.
+pub fn print_changes(txn: &Txn,
+                     opts: &BasicOptions,
+                     branch: &Branch,
+                     filters: &Filters,
+                     limit: Option<usize>,
+                     format: Format)
+                     -> Result<(), Error> {
+    let mut json = Vec::new();
+    let mut count = 0;
+    for (_, internal) in txn.rev_iter_applied(branch, None) {
+        if let Some(limit) = limit {
+            if count >= limit {
+                break
             }
         }
+        let hash_ext = txn.get_external(&internal).unwrap();
+        let patch = if filters.paths.is_empty() {
+            read_patch_nochanges(&opts.repo_root, hash_ext)?
+        } else {
+            let patch = read_patch(&opts.repo_root, hash_ext)?;
+            if !filters.paths.iter().any(|keys| txn.patch_touches(keys, &internal, &patch)) {
+                continue
+            }
+            patch.header
+        };
+        if !filters.matches_header(&patch) {
+            continue
+        }
+        count += 1;
+        let hash_ext = hash_ext.to_owned();
+        match format {
+            Format::Full => ask::print_patch_descr(&hash_ext, &patch),
+            Format::OneLine => {
+                println!("{} {} {}",
+                         hash_ext.to_base64(URL_SAFE),
+                         patch.timestamp.format("%F"),
+                         patch.name)
+            }
+            Format::Json => json.push(patch_to_json(&hash_ext, &patch)),
+            Format::Template(template) => {
+                println!("{}", fill_template(template, &hash_ext, &patch))
+            }
+        }
+    }
+    if let Format::Json = format {
+        println!("{}", Json::Array(json).pretty());
     }
     Ok(())
 }
@@ -1,10 +1,12 @@
 use clap::{SubCommand, ArgMatches, Arg};
+use chrono;
 
 use super::{BasicOptions, StaticSubcommand, set_current_branch, get_current_branch, default_explain};
 use rand;
 use error::Error;
+use meta::Meta;
 
-use libpijul::Patch;
+use libpijul::{MutTxn, Patch, DEFAULT_BRANCH};
 
 pub fn invocation() -> StaticSubcommand {
     return SubCommand::with_name("checkout")
@@ -19,17 +21,70 @@ pub fn invocation() -> StaticSubcommand {
              .help("Branch to switch to.")
              .takes_value(true)
         )
+        .arg(Arg::with_name("carry")
+             .long("carry")
+             .help("Carry the unrecorded changes of the working copy over to the new branch.")
+        )
+        .arg(Arg::with_name("force")
+             .long("force")
+             .help("Discard the unrecorded changes of the working copy.")
+             .conflicts_with("carry")
+        )
+}
+
+/// Tells whether all the dependencies of `patch` are applied on
+/// branch `branch_name`, so that it can be applied there.
+fn can_apply<R: rand::Rng>(txn: &MutTxn<R>, branch_name: &str, patch: &Patch) -> bool {
+    let branch = match txn.get_branch(branch_name) {
+        Some(branch) => branch,
+        None => return false,
+    };
+    patch.dependencies.iter().all(|dep| {
+        match txn.get_internal(dep.as_ref()) {
+            Some(internal) => txn.get_patch(&branch.patches, &internal).is_some(),
+            None => false,
+        }
+    })
 }
 
 pub fn run(args: &ArgMatches) -> Result<(), Error> {
     let opts = BasicOptions::from_args(args)?;
     if let Some(branch) = args.value_of("branch") {
-        let repo = opts.open_repo()?;
+        let current_branch = get_current_branch(&opts.repo_root)
+            .unwrap_or(DEFAULT_BRANCH.to_string());
+        let repo = opts.open_and_grow_repo(409600)?;
         let mut txn = try!(repo.mut_txn_begin(rand::thread_rng()));
 
         if txn.get_branch(branch).is_some() {
+            let defaults = Meta::load(&opts.repo_root).file_attributes();
+
+            // Unrecorded changes would be overwritten when outputting
+            // the new branch: either refuse, or apply them to the new
+            // branch as a pending patch.
+            let (pending, pending_syncs) = if args.is_present("force") {
+                (Patch::empty(), Vec::new())
+            } else {
+                let (changes, syncs) =
+                    txn.record(&current_branch, &opts.repo_root, &[], false, &defaults)?;
+                if changes.is_empty() {
+                    (Patch::empty(), Vec::new())
+                } else if args.is_present("carry") {
+                    let changes = changes.into_iter().flat_map(|x| x.into_iter()).collect();
+                    let patch = {
+                        let current = txn.get_branch(&current_branch).unwrap();
+                        txn.new_patch(&current, Vec::new(), String::new(), None,
+                                      chrono::UTC::now(), changes)
+                    };
+                    if !can_apply(&txn, branch, &patch) {
+                        return Err(Error::CannotCarryChanges(branch.to_string()))
+                    }
+                    (patch, syncs)
+                } else {
+                    return Err(Error::PendingChanges)
+                }
+            };
 
-            txn.output_repository(&branch, &opts.repo_root, &Patch::empty(), &Vec::new())?;
+            txn.output_repository(&branch, &opts.repo_root, &pending, &pending_syncs, &defaults)?;
             txn.commit()?;
 
             set_current_branch(&opts.repo_root, branch)?;
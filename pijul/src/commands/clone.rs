@@ -2,7 +2,8 @@ use clap::{SubCommand, ArgMatches, Arg};
 
 use commands::{assert_no_containing_repo, create_repo, default_explain, StaticSubcommand};
 use error::Error;
-use commands::remote::{Remote, parse_remote};
+use commands::remote::{Remote, find_remote, parse_remote};
+use meta::Meta;
 use regex::Regex;
 use libpijul::DEFAULT_BRANCH;
 use std::io::{Write, stderr};
@@ -12,7 +13,7 @@ pub fn invocation() -> StaticSubcommand {
     return SubCommand::with_name("clone")
         .about("clone a remote branch")
         .arg(Arg::with_name("from")
-            .help("Repository to clone.")
+            .help("Repository to clone, or the name of a remote in the global configuration.")
             .required(true))
         .arg(Arg::with_name("from_branch")
             .long("from-branch")
@@ -23,6 +24,12 @@ pub fn invocation() -> StaticSubcommand {
             .help("The branch to pull into")
             .takes_value(true))
         .arg(Arg::with_name("to").help("Target."))
+        .arg(Arg::with_name("identity")
+            .short("i")
+            .long("identity")
+            .help("Private key to authenticate to the remote ssh server with. Defaults \
+                   to ~/.ssh/id_ed25519, then to ~/.ssh/id_rsa.")
+            .takes_value(true))
         .arg(Arg::with_name("port")
             .short("p")
             .long("port")
@@ -44,27 +51,25 @@ pub struct Params<'a> {
     pub to_branch: &'a str,
 }
 
-pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
+pub fn parse_args<'a>(args: &'a ArgMatches, meta: &'a Meta) -> Params<'a> {
     // At least one must not use its "port" argument
-    let from = parse_remote(args.value_of("from").unwrap(),
-                            args.value_of("port").and_then(|x| Some(x.parse().unwrap())),
-                            None);
+    let port = args.value_of("port").and_then(|x| Some(x.parse().unwrap()));
+    let identity = args.value_of("identity");
+    let (from, named) = find_remote(meta, args.value_of("from").unwrap(), port, identity, None, None);
     let to = if let Some(to) = args.value_of("to") {
-        parse_remote(to,
-                     args.value_of("port").and_then(|x| Some(x.parse().unwrap())),
-                     None)
+        find_remote(meta, to, port, identity, None, None).0
     } else {
         let basename = Regex::new(r"([^/:]*)").unwrap();
-        let from = args.value_of("from").unwrap();
+        let from = named.map(|n| n.address.as_str()).unwrap_or(args.value_of("from").unwrap());
         if let Some(to) = basename.captures_iter(from).last().and_then(|to| to.get(1)) {
-            parse_remote(to.as_str(),
-                         args.value_of("port").and_then(|x| Some(x.parse().unwrap())),
-                         None)
+            parse_remote(to.as_str(), port, None)
         } else {
             panic!("Could not parse target")
         }
     };
-    let from_branch = args.value_of("from_branch").unwrap_or(DEFAULT_BRANCH);
+    let from_branch = args.value_of("from_branch")
+        .or(named.and_then(|n| n.pull_branch.as_ref().map(|b| b.as_str())))
+        .unwrap_or(DEFAULT_BRANCH);
     let to_branch = args.value_of("to_branch").unwrap_or(from_branch);
     Params {
         from: from,
@@ -77,7 +82,8 @@ pub fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
 
 
 pub fn run(args: &ArgMatches) -> Result<(), Error> {
-    let args = parse_args(args);
+    let meta = Meta::load_global();
+    let args = parse_args(args, &meta);
     debug!("{:?}", args);
     match args.from {
         Remote::Local { ref path } => {
@@ -0,0 +1,105 @@
+use clap::{SubCommand, ArgMatches, Arg};
+use commands::{BasicOptions, StaticSubcommand, default_explain};
+use libpijul::{PatchId, Txn};
+use libpijul::conflict::FileConflicts;
+use rustc_serialize::base64::{ToBase64, URL_SAFE};
+use rustc_serialize::hex::ToHex;
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::BTreeMap;
+use error::Error;
+
+pub fn invocation() -> StaticSubcommand {
+    return SubCommand::with_name("conflicts")
+        .about("List the conflicts of a branch")
+        .arg(Arg::with_name("repository")
+             .long("repository")
+             .help("Path to a pijul repository. Defaults to the repository containing the \
+                    current directory.")
+             .takes_value(true))
+        .arg(Arg::with_name("branch")
+             .long("branch")
+             .help("The branch to examine. Defaults to the current branch.")
+             .takes_value(true))
+        .arg(Arg::with_name("json")
+             .long("json")
+             .help("Output the conflicts in JSON."))
+}
+
+pub fn run(args: &ArgMatches) -> Result<(), Error> {
+    let opts = BasicOptions::from_args(args)?;
+    let repo = opts.open_repo()?;
+    let txn = repo.txn_begin()?;
+    let conflicts = if let Some(branch) = txn.get_branch(&opts.branch()) {
+        txn.list_conflicts(&branch)?
+    } else {
+        Vec::new()
+    };
+    if args.is_present("json") {
+        let files: Vec<Json> = conflicts.iter().map(|f| file_to_json(&txn, f)).collect();
+        println!("{}", Json::Array(files).pretty());
+    } else {
+        for file in conflicts.iter() {
+            print_file(&txn, file)
+        }
+    }
+    Ok(())
+}
+
+/// The hash of the patch with internal id `patch`, in base64.
+fn patch_hash(txn: &Txn, patch: &PatchId) -> String {
+    match txn.get_external(patch) {
+        Some(hash) => hash.to_base64(URL_SAFE),
+        None => patch.to_hex(),
+    }
+}
+
+fn print_file(txn: &Txn, file: &FileConflicts) {
+    println!("{}", file.path.display());
+    for conflict in file.conflicts.iter() {
+        if conflict.has_zombies() && conflict.sides.len() <= 1 {
+            println!("  line {}: zombie lines", conflict.line);
+        } else {
+            println!("  line {}: {} sides", conflict.line, conflict.sides.len());
+        }
+        for (i, side) in conflict.sides.iter().enumerate() {
+            let patches: Vec<_> = side.patches().iter().map(|p| patch_hash(txn, p)).collect();
+            let zombies = side.lines.iter().filter(|l| l.is_zombie).count();
+            print!("    side {}: {} line{}", i + 1, side.lines.len(),
+                   if side.lines.len() == 1 { "" } else { "s" });
+            if zombies > 0 {
+                print!(" ({} zombie{})", zombies, if zombies == 1 { "" } else { "s" });
+            }
+            println!(", from {}", patches.join(", "));
+        }
+    }
+}
+
+fn file_to_json(txn: &Txn, file: &FileConflicts) -> Json {
+    let mut f = BTreeMap::new();
+    f.insert("path".to_string(), file.path.to_string_lossy().to_json());
+    let conflicts = file.conflicts.iter().map(|conflict| {
+        let mut c = BTreeMap::new();
+        c.insert("line".to_string(), conflict.line.to_json());
+        let sides = conflict.sides.iter().map(|side| {
+            let lines = side.lines.iter().map(|line| {
+                let mut l = BTreeMap::new();
+                let patch = line.key.patch;
+                l.insert("patch".to_string(), patch_hash(txn, &patch).to_json());
+                l.insert("key".to_string(), line.key.to_hex().to_json());
+                l.insert("zombie".to_string(), line.is_zombie.to_json());
+                l.insert("contents".to_string(),
+                         String::from_utf8_lossy(&line.contents).to_json());
+                Json::Object(l)
+            }).collect();
+            Json::Array(lines)
+        }).collect();
+        c.insert("sides".to_string(), Json::Array(sides));
+        Json::Object(c)
+    }).collect();
+    f.insert("conflicts".to_string(), Json::Array(conflicts));
+    Json::Object(f)
+}
+
+pub fn explain(res: Result<(), Error>) {
+    default_explain(res)
+}
@@ -3,6 +3,7 @@ use rand;
 
 use commands::{BasicOptions, StaticSubcommand, default_explain};
 use error::Error;
+use meta::Meta;
 
 pub fn invocation() -> StaticSubcommand {
     return SubCommand::with_name("diff")
@@ -17,6 +18,16 @@ pub fn invocation() -> StaticSubcommand {
              .help("The branch to show, defaults to the current branch.")
              .takes_value(true)
              .required(false))
+        .arg(Arg::with_name("untracked")
+             .long("untracked")
+             .help("List the files that are neither in the repository nor ignored, instead of showing the changes."))
+        .arg(Arg::with_name("full-check")
+             .long("full-check")
+             .help("Read all files, including those that look unchanged since the last check."))
+        .arg(Arg::with_name("prefix")
+             .help("Only show changes in these files or directories.")
+             .multiple(true)
+             .takes_value(true))
 }
 
 pub fn run(args: &ArgMatches) -> Result<(), Error> {
@@ -26,9 +37,33 @@ pub fn run(args: &ArgMatches) -> Result<(), Error> {
     // write is one write in the branches table, affecting
     // at most O(log n) blocks.
     let repo = opts.open_and_grow_repo(409600)?;
+    if args.is_present("untracked") {
+        let txn = repo.txn_begin()?;
+        for file in txn.untracked_files(&opts.repo_root)? {
+            println!("{}", file.display())
+        }
+        return Ok(())
+    }
+    let prefixes = match args.values_of("prefix") {
+        Some(p) => p.map(|p| opts.relative_path(p)).collect::<Result<Vec<_>, _>>()?,
+        None => Vec::new(),
+    };
     let mut txn = repo.mut_txn_begin(rand::thread_rng())?;
-    let (changes, _) = txn.record(&opts.branch(), &opts.repo_root)?;
-    try!(super::ask::print_status(&txn, &changes));
+    let full_check = args.is_present("full-check");
+    let defaults = Meta::load(&opts.repo_root).file_attributes();
+    if let Some(branch) = txn.get_branch(&opts.branch()) {
+        let (changes, _) =
+            txn.diff_working_copy(&branch, &opts.repo_root, &prefixes, full_check, &defaults)?;
+        try!(super::ask::print_status(&txn, &changes));
+        // Save the stamps of unchanged files.
+        txn.commit()?;
+    } else {
+        // Nothing was recorded on this branch yet. Compare with an
+        // empty branch, without committing it.
+        let (changes, _) =
+            txn.record(&opts.branch(), &opts.repo_root, &prefixes, full_check, &defaults)?;
+        try!(super::ask::print_status(&txn, &changes));
+    }
     Ok(())
 }
 
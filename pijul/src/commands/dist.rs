@@ -3,7 +3,7 @@ use clap::{SubCommand, ArgMatches, Arg};
 use error;
 use libpijul::{ROOT_KEY, Branch, Txn, Key, PatchId, Edge};
 use std::path::{PathBuf, Path};
-use tar::{Header, Builder};
+use tar::{Header, Builder, EntryType};
 use std::fs::File;
 use flate2::write::GzEncoder;
 use flate2::Compression;
@@ -71,10 +71,18 @@ pub fn archive_rec<W:Write>(
             txn.output_file(buffer, &mut graph, forward)?;
             let mut header = Header::new_gnu();
             header.set_path(&current_path)?;
-            header.set_size(buffer.len() as u64);
             header.set_mode(names[0].0.permissions() as u32);
-            header.set_cksum();
-            builder.append(&header, &buffer[..])?;
+            if names[0].0.is_symlink() {
+                header.set_entry_type(EntryType::Symlink);
+                header.set_link_name(&*String::from_utf8_lossy(&buffer[..]))?;
+                header.set_size(0);
+                header.set_cksum();
+                builder.append(&header, &[][..])?;
+            } else {
+                header.set_size(buffer.len() as u64);
+                header.set_cksum();
+                builder.append(&header, &buffer[..])?;
+            }
         }
         current_path.pop();
     }
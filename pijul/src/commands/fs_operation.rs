@@ -1,7 +1,9 @@
 use clap::ArgMatches;
 use libpijul::Repository;
-use std::path::Path;
-use std::fs::{metadata, canonicalize};
+use libpijul::ignore::Ignore;
+use std::path::{Path, PathBuf};
+use std::fs::{symlink_metadata, canonicalize};
+use std::io::{Write, stderr};
 use error;
 use commands::BasicOptions;
 use rand;
@@ -31,16 +33,30 @@ pub fn run(args: &ArgMatches, op: Operation) -> Result<(), error::Error> {
     }
 }
 
+/// Canonicalizes the parent of `path`, but not `path` itself, so as
+/// to refer to symbolic links rather than to their targets.
+fn absolute_path(path: &Path) -> Result<PathBuf, Error> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if parent != Path::new("") => Ok(canonicalize(parent)?.join(name)),
+        _ => Ok(canonicalize(path)?),
+    }
+}
+
 fn really_run(repo_dir: &Path, wd: &Path, r: &Path, files: &[&Path], op: Operation, extra_space: u64) -> Result<(), Error> {
     let mut rng = rand::thread_rng();
     let repo = Repository::open(&repo_dir, Some(extra_space))?;
     let mut txn = repo.mut_txn_begin(&mut rng)?;
     match op {
         Operation::Add => {
+            let mut ignore = Ignore::new(r);
             for file in &files[..] {
-                let p = canonicalize(wd.join(*file))?;
-                let m = metadata(&p)?;
+                let p = absolute_path(&wd.join(*file))?;
+                let m = symlink_metadata(&p)?;
                 if let Ok(file) = p.strip_prefix(r) {
+                    if ignore.is_ignored(file, m.is_dir())? {
+                        let _ = writeln!(stderr(), "Not adding ignored file {}", file.display());
+                        continue
+                    }
                     txn.add_file(file, m.is_dir())?
                 } else {
                     return Err(Error::InvalidPath(file.to_string_lossy().into_owned()));
@@ -49,7 +65,7 @@ fn really_run(repo_dir: &Path, wd: &Path, r: &Path, files: &[&Path], op: Operati
         }
         Operation::Remove => {
             for file in &files[..] {
-                let p = canonicalize(wd.join(*file))?;
+                let p = absolute_path(&wd.join(*file))?;
                 if let Ok(file) = p.strip_prefix(r) {
                     txn.remove_file(file)?
                 } else {
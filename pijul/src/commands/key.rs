@@ -0,0 +1,66 @@
+use clap::{SubCommand, ArgMatches, Arg};
+use commands::{StaticSubcommand, default_explain};
+use libpijul::signature::PublicKey;
+use meta::KeyStore;
+use error::Error;
+
+pub fn invocation() -> StaticSubcommand {
+    return SubCommand::with_name("key")
+        .about("Manage the keys used to sign and verify patches")
+        .subcommand(SubCommand::with_name("generate")
+                    .about("Generate a new signing key, replacing the current one"))
+        .subcommand(SubCommand::with_name("show")
+                    .about("Print the public key corresponding to the signing key"))
+        .subcommand(SubCommand::with_name("trust")
+                    .about("Trust patches signed by a public key")
+                    .arg(Arg::with_name("name")
+                         .help("A name for this key, for instance its owner's.")
+                         .required(true))
+                    .arg(Arg::with_name("public_key")
+                         .help("The public key, in base64.")
+                         .required(true)))
+        .subcommand(SubCommand::with_name("untrust")
+                    .about("Stop trusting a public key")
+                    .arg(Arg::with_name("name")
+                         .help("The name of the key.")
+                         .required(true)))
+        .subcommand(SubCommand::with_name("list")
+                    .about("List the trusted public keys"))
+}
+
+pub fn run(args: &ArgMatches) -> Result<(), Error> {
+    let mut keys = KeyStore::load()?;
+    match args.subcommand() {
+        ("generate", Some(_)) => {
+            let public_key = keys.generate()?;
+            keys.save()?;
+            println!("Generated a new signing key, saved in {}.", KeyStore::path()?.display());
+            println!("Public key: {}", public_key.to_base64());
+        }
+        ("show", Some(_)) => {
+            println!("{}", keys.signing_key()?.public_key().to_base64())
+        }
+        ("trust", Some(args)) => {
+            let key = args.value_of("public_key").unwrap();
+            let public_key = PublicKey::from_base64(key)
+                .ok_or(Error::InvalidPublicKey(key.to_string()))?;
+            keys.trust(args.value_of("name").unwrap(), &public_key);
+            keys.save()?;
+        }
+        ("untrust", Some(args)) => {
+            let name = args.value_of("name").unwrap();
+            keys.trusted.retain(|k| k.name != name);
+            keys.save()?;
+        }
+        _ => {
+            for k in keys.trusted.iter() {
+                println!("{} {}", k.name, k.public_key)
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn explain(res: Result<(), Error>) {
+    default_explain(res)
+}
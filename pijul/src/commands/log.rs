@@ -0,0 +1,57 @@
+use clap::{SubCommand, ArgMatches, Arg};
+use commands::{BasicOptions, StaticSubcommand, default_explain};
+use commands::changes::{Filters, Format, parse_limit, print_changes};
+use error::Error;
+
+pub fn invocation() -> StaticSubcommand {
+    SubCommand::with_name("log")
+        .about("List the patches that touched a file or directory, most recent first")
+        .arg(Arg::with_name("repository")
+            .long("repository")
+            .help("Path to a pijul repository. Defaults to the repository containing the \
+                   current directory.")
+            .takes_value(true))
+        .arg(Arg::with_name("branch")
+            .long("branch")
+            .help("The branch to get the history from. Defaults to the current branch.")
+            .takes_value(true))
+        .arg(Arg::with_name("limit")
+            .short("n")
+            .long("limit")
+            .help("List at most this number of patches.")
+            .takes_value(true))
+        .arg(Arg::with_name("format")
+            .long("format")
+            .help("Output format.")
+            .takes_value(true)
+            .possible_values(&["full", "one-line", "json"]))
+        .arg(Arg::with_name("template")
+            .long("template")
+            .help("Print each patch according to this template, in which {hash}, {name}, \
+                   {authors}, {description}, {timestamp} and {date} are replaced by the \
+                   corresponding field of the patch.")
+            .takes_value(true)
+            .conflicts_with("format"))
+        .arg(Arg::with_name("path")
+            .help("The file or directory. Its history includes the patches that moved it.")
+            .required(true)
+            .takes_value(true))
+}
+
+pub fn run(args: &ArgMatches) -> Result<(), Error> {
+    let opts = BasicOptions::from_args(args)?;
+    let limit = parse_limit(args)?;
+    let repo = opts.open_repo()?;
+    let txn = repo.txn_begin()?;
+    let branch = match txn.get_branch(&opts.branch()) {
+        Some(branch) => branch,
+        None => return Err(Error::NoSuchBranch),
+    };
+    // The only filter set by the arguments of log is the path.
+    let filters = Filters::from_args(&txn, &opts, &branch, args)?;
+    print_changes(&txn, &opts, &branch, &filters, limit, Format::from_args(args))
+}
+
+pub fn explain(r: Result<(), Error>) {
+    default_explain(r)
+}
@@ -5,6 +5,7 @@ pub type StaticSubcommand = clap::App<'static, 'static>;
 mod fs_operation;
 mod remote;
 mod ask;
+mod select;
 
 pub mod info;
 pub mod init;
@@ -28,6 +29,14 @@ pub mod checkout;
 pub mod diff;
 pub mod blame;
 pub mod dist;
+pub mod key;
+pub mod conflicts;
+pub mod resolve;
+pub mod status;
+pub mod stash;
+pub mod log;
+pub mod serve;
+pub mod remotes;
 
 #[cfg(test)]
 mod test;
@@ -64,6 +73,14 @@ pub fn all_command_invocations() -> Vec<StaticSubcommand> {
                 diff::invocation(),
                 blame::invocation(),
                 dist::invocation(),
+                key::invocation(),
+                conflicts::invocation(),
+                resolve::invocation(),
+                status::invocation(),
+                stash::invocation(),
+                log::invocation(),
+                serve::invocation(),
+                remotes::invocation(),
     ];
 }
 
@@ -187,5 +204,25 @@ impl<'a> BasicOptions<'a> {
     pub fn pristine_dir(&self) -> PathBuf {
         fs_representation::pristine_dir(&self.repo_root)
     }
+
+    /// Converts a path given on the command line to a path relative
+    /// to the repository root. The path does not need to exist, as
+    /// long as its parent does.
+    pub fn relative_path(&self, path: &str) -> Result<PathBuf, Error> {
+        let full = self.cwd.join(path);
+        let full = match canonicalize(&full) {
+            Ok(full) => full,
+            Err(e) => {
+                match (full.parent(), full.file_name()) {
+                    (Some(parent), Some(name)) => canonicalize(parent)?.join(name),
+                    _ => return Err(e.into()),
+                }
+            }
+        };
+        match full.strip_prefix(&self.repo_root) {
+            Ok(relative) => Ok(relative.to_path_buf()),
+            Err(_) => Err(Error::InvalidPath(path.to_string())),
+        }
+    }
 }
 
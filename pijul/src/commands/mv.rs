@@ -2,7 +2,7 @@ use commands::{BasicOptions, StaticSubcommand, default_explain};
 use clap::{SubCommand, ArgMatches, Arg};
 use error;
 use std::path::PathBuf;
-use std::fs::{rename, metadata};
+use std::fs::{rename, symlink_metadata};
 
 use std;
 use rand;
@@ -96,7 +96,7 @@ pub fn run(args: &ArgMatches) -> Result<(), error::Error> {
                                                .ok_or(error::Error::InvalidPath(f.to_string_lossy().into_owned())));
                     dest_dir.as_path().join(&target_basename)
                 };
-                let is_dir = try!(metadata(&opts.repo_root.join(f))).is_dir();
+                let is_dir = try!(symlink_metadata(&opts.repo_root.join(f))).is_dir();
                 try!(txn.move_file(f, &repo_target_name.as_path(), is_dir));
             }
             for file in orig_paths {
@@ -1,22 +1,36 @@
 use clap::{SubCommand, ArgMatches, Arg};
 use commands::{BasicOptions, StaticSubcommand, default_explain};
-use libpijul::{Hash};
-use libpijul::fs_representation::patches_dir;
+use libpijul::{Hash, LineId, Key, Txn, DELETED_EDGE, FOLDER_EDGE};
+use libpijul::fs_representation::{patches_dir, read_patch};
+use libpijul::patch::{Change, EdgeOp, Patch, Record};
 
 use error::Error;
 use rustc_serialize::base64::{ToBase64, URL_SAFE};
+use std::collections::{HashMap, HashSet};
 use std::io::{stdout, copy};
 use std::fs::File;
+use std::path::PathBuf;
+use std::rc::Rc;
 
 pub fn invocation() -> StaticSubcommand {
     return SubCommand::with_name("patch")
-        .about("Output a patch (in binary)")
+        .about("Output a patch, in binary or as a readable diff")
 
         .arg(Arg::with_name("repository")
              .long("repository")
              .help("Path to the repository where the patches will be applied.")
              .takes_value(true))
 
+        .arg(Arg::with_name("branch")
+             .long("branch")
+             .help("The branch against which the patch is shown with --text. Defaults to the \
+                    current branch.")
+             .takes_value(true))
+
+        .arg(Arg::with_name("text")
+             .long("text")
+             .help("Show the patch as a readable diff instead of its binary form."))
+
         .arg(Arg::with_name("patch")
              .help("The hash of the patch to be printed.")
              .takes_value(true)
@@ -34,6 +48,18 @@ pub fn run(args: &ArgMatches) -> Result<(), Error> {
     let opts = BasicOptions::from_args(args)?;
     // FIXME: the second panic could unwrap
     let patch = Hash::from_base64(args.value_of("patch").unwrap()).unwrap();
+    if args.is_present("text") {
+        let repo = opts.open_repo()?;
+        let txn = repo.txn_begin()?;
+        let branch = match txn.get_branch(&opts.branch()) {
+            Some(branch) => branch,
+            None => return Err(Error::NoSuchBranch),
+        };
+        let patch = read_patch(&opts.repo_root, patch.as_ref())?;
+        let records = txn.patch_records(&branch, &patch);
+        print_text(&txn, &patch, &records);
+        return Ok(())
+    }
     let mut patch_path = patches_dir(opts.repo_root).join(&patch.to_base64(URL_SAFE));
     patch_path.set_extension("gz");
     let mut f = try!(File::open(&patch_path));
@@ -42,6 +68,112 @@ pub fn run(args: &ArgMatches) -> Result<(), Error> {
     Ok(())
 }
 
+/// Prints a line of a file, prefixed by `prefix`.
+fn print_line(prefix: char, line: &[u8]) {
+    match ::std::str::from_utf8(line) {
+        Ok(s) if s.ends_with("\n") => print!("{}{}", prefix, s),
+        Ok(s) => println!("{}{}\n\\ No newline at end of file", prefix, s),
+        Err(_) => println!("{}<binary contents>", prefix),
+    }
+}
+
+/// The contents of the line with key `key`, which is either
+/// introduced by the patch, or in the repository.
+fn line_contents(txn: &Txn, local: &HashMap<LineId, &[u8]>, key: &Key<Option<Hash>>) -> Option<Vec<u8>> {
+    if key.patch.is_none() {
+        local.get(&key.line).map(|l| l.to_vec())
+    } else {
+        txn.internal_key_opt(key)
+            .and_then(|k| txn.get_contents(&k))
+            .map(|c| c.into_cow().into_owned())
+    }
+}
+
+fn print_text(txn: &Txn, patch: &Patch, records: &[Record]) {
+    let mut local = HashMap::new();
+    for change in patch.changes.iter() {
+        if let Change::NewNodes { ref line_num, ref nodes, .. } = *change {
+            for (i, n) in nodes.iter().enumerate() {
+                local.insert(*line_num + i, &n[..]);
+            }
+        }
+    }
+    let mut current_file: Option<Rc<PathBuf>> = None;
+    for record in records {
+        match *record {
+            Record::FileAdd { ref name, .. } => {
+                println!("added file {}", name);
+                current_file = None
+            }
+            Record::FileDel { ref name, .. } => {
+                println!("deleted file {}", name);
+                current_file = None
+            }
+            Record::FileMove { ref new_name, .. } => {
+                println!("moved file to {}", new_name);
+                current_file = None
+            }
+            Record::Change { ref file, ref change, .. } |
+            Record::Replace { ref file, adds: ref change, .. } => {
+                if current_file.as_ref() != Some(file) {
+                    println!("--- {}", file.display());
+                    println!("+++ {}", file.display());
+                    current_file = Some(file.clone())
+                }
+                if let Record::Replace { ref dels, .. } = *record {
+                    print_change(txn, &local, dels)
+                }
+                print_change(txn, &local, change)
+            }
+        }
+    }
+}
+
+fn print_change(txn: &Txn, local: &HashMap<LineId, &[u8]>, change: &Change) {
+    match *change {
+        Change::NewNodes { ref up_context, ref down_context, ref flag, ref nodes, .. } => {
+            if flag.contains(FOLDER_EDGE) {
+                return
+            }
+            println!("@@ @@");
+            // Context lines, skipping file nodes, which are empty.
+            for up in up_context.iter().take(1) {
+                match line_contents(txn, local, up) {
+                    Some(ref l) if !l.is_empty() => print_line(' ', l),
+                    _ => {}
+                }
+            }
+            for n in nodes.iter() {
+                print_line('+', n)
+            }
+            for down in down_context.iter().take(1) {
+                match line_contents(txn, local, down) {
+                    Some(ref l) if !l.is_empty() => print_line(' ', l),
+                    _ => {}
+                }
+            }
+        }
+        Change::NewEdges { ref edges, ref op } => {
+            let prefix = match *op {
+                EdgeOp::Map { ref flag, .. } if flag.contains(DELETED_EDGE) => '-',
+                EdgeOp::Map { ref previous, .. } if previous.contains(DELETED_EDGE) => '+',
+                _ => return,
+            };
+            println!("@@ @@");
+            let mut seen = HashSet::new();
+            for e in edges {
+                if seen.insert(&e.from) {
+                    if let Some(l) = line_contents(txn, local, &e.from) {
+                        if !l.is_empty() {
+                            print_line(prefix, &l)
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
 pub fn explain(r: Result<(), Error>) {
     default_explain(r)
 }
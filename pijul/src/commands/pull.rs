@@ -6,21 +6,25 @@ use std::path::Path;
 use std::fs::File;
 
 use libpijul::patch::Patch;
-use libpijul::{Hash, DEFAULT_BRANCH, ApplyTimestamp};
+use libpijul::{self, Hash, DEFAULT_BRANCH, ApplyTimestamp};
 use commands::remote;
 use commands::ask::{ask_patches, Command};
+use commands::select::{self, Selector};
+use libpijul::fs_representation::pristine_dir;
 use std::io::BufReader;
 
-use super::super::meta::{Meta, Repository};
+use super::super::meta::{Meta, NamedRemote, Repository};
 
 pub fn invocation() -> StaticSubcommand {
-    return SubCommand::with_name("pull")
+    let cmd = SubCommand::with_name("pull")
         .about("pull from a remote repository")
-        .arg(Arg::with_name("remote").help("Repository from which to pull."))
+        .arg(Arg::with_name("remote")
+            .help("Repository from which to pull, or the name of a remote."))
         .arg(Arg::with_name("repository").help("Local repository."))
         .arg(Arg::with_name("remote_branch")
             .long("from-branch")
-            .help("The branch to pull from. Defaults to master.")
+            .help("The branch to pull from. Defaults to the pull branch of the remote, \
+                   or to master.")
             .takes_value(true))
         .arg(Arg::with_name("local_branch")
             .long("to-branch")
@@ -32,6 +36,12 @@ pub fn invocation() -> StaticSubcommand {
             .help("Answer 'y' to all questions")
             .takes_value(false))
         .arg(Arg::with_name("set-default").long("set-default"))
+        .arg(Arg::with_name("identity")
+            .short("i")
+            .long("identity")
+            .help("Private key to authenticate to the remote ssh server with. Defaults \
+                   to ~/.ssh/id_ed25519, then to ~/.ssh/id_rsa.")
+            .takes_value(true))
         .arg(Arg::with_name("port")
             .short("p")
             .long("port")
@@ -44,6 +54,7 @@ pub fn invocation() -> StaticSubcommand {
                     Err(_) => Err(val),
                 }
             }));
+    select::add_args(cmd)
 }
 
 #[derive(Debug)]
@@ -52,8 +63,9 @@ pub struct Params<'a> {
     pub yes_to_all: bool,
     pub set_default: bool,
     pub port: Option<u16>,
+    pub identity: Option<&'a str>,
     pub local_branch: &'a str,
-    pub remote_branch: &'a str,
+    pub remote_branch: Option<&'a str>,
 }
 
 fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
@@ -64,29 +76,36 @@ fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
         yes_to_all: args.is_present("all"),
         set_default: args.is_present("set-default"),
         port: args.value_of("port").and_then(|x| Some(x.parse().unwrap())),
+        identity: args.value_of("identity"),
         local_branch: args.value_of("local_branch").unwrap_or(DEFAULT_BRANCH),
-        remote_branch: args.value_of("remote_branch").unwrap_or(DEFAULT_BRANCH),
+        remote_branch: args.value_of("remote_branch"),
     }
 }
 
 fn get_remote<'a>(args: &Params<'a>,
                   meta: &'a Meta,
                   repo_root: &'a Path)
-                  -> Result<(bool, remote::Remote<'a>), Error> {
-    match args.remote_id {
-        Some(remote_id) => Ok((true, remote::parse_remote(remote_id, args.port, None))),
+                  -> Result<(bool, remote::Remote<'a>, Option<&'a NamedRemote>), Error> {
+    let repo_root = Some(repo_root);
+    let (savable, (remote, named)) = match args.remote_id {
+        Some(remote_id) => {
+            (true, remote::find_remote(meta, remote_id, args.port, args.identity, None, repo_root))
+        }
         None => {
             match meta.pull {
                 Some(Repository::SSH { ref address, ref port }) => {
-                    Ok((false, remote::parse_remote(address, Some(*port), Some(repo_root))))
+                    (false, remote::find_remote(meta, address, Some(*port), args.identity,
+                                                repo_root, repo_root))
                 }
                 Some(Repository::String(ref host)) => {
-                    Ok((false, remote::parse_remote(host, None, Some(repo_root))))
+                    (false, remote::find_remote(meta, host, None, args.identity,
+                                                repo_root, repo_root))
                 }
-                None => Err(Error::MissingRemoteRepository),
+                None => return Err(Error::MissingRemoteRepository),
             }
         }
-    }
+    };
+    Ok((savable, remote, named))
 }
 
 fn fetch_pullable_patches(session: &mut remote::Session,
@@ -108,7 +127,10 @@ fn fetch_pullable_patches(session: &mut remote::Session,
     Ok(patches)
 }
 
+/// Selects the patches to pull, using `selector` if there is one,
+/// or else interactively if `interactive` is true.
 pub fn select_patches(interactive: bool,
+                      selector: Option<&Selector>,
                       session: &mut remote::Session,
                       remote_branch: &str,
                       local_branch: &str,
@@ -117,7 +139,18 @@ pub fn select_patches(interactive: bool,
     let pullable = try!(session.pullable_patches(remote_branch, local_branch, r));
     let mut pullable:Vec<_> = pullable.iter().collect();
     pullable.sort_by(|&(_, a), &(_, b)| a.cmp(&b));
-    if interactive {
+    if let Some(selector) = selector {
+        let selected = {
+            let patches = try!(fetch_pullable_patches(session, &pullable, r));
+            let repo = libpijul::Repository::open(&pristine_dir(r), None)?;
+            let txn = repo.txn_begin()?;
+            let branch = txn.get_branch(local_branch);
+            selector.select(&txn, branch.as_ref(), &patches)
+        };
+        Ok(pullable.into_iter()
+           .filter(|&(ref h, _)| selected.contains(h))
+           .collect())
+    } else if interactive {
         let selected = {
             let patches = try!(fetch_pullable_patches(session, &pullable, r));
             try!(ask_patches(Command::Pull, &patches[..]))
@@ -135,11 +168,16 @@ pub fn run(arg_matches: &ArgMatches) -> Result<(), Error> {
     let args = parse_args(arg_matches);
     debug!("pull args {:?}", args);
     let meta = Meta::load(&opts.repo_root);
-    let (savable, remote) = try!(get_remote(&args, &meta, &opts.repo_root));
+    let (savable, remote, named) = try!(get_remote(&args, &meta, &opts.repo_root));
+    let remote_branch = args.remote_branch
+        .or(named.and_then(|n| n.pull_branch.as_ref().map(|b| b.as_str())))
+        .unwrap_or(DEFAULT_BRANCH);
     let mut session = try!(remote.session());
+    let selector = Selector::from_args(arg_matches)?;
     let pullable = try!(select_patches(!args.yes_to_all,
+                                       selector.as_ref(),
                                        &mut session,
-                                       args.remote_branch,
+                                       remote_branch,
                                        args.local_branch,
                                        &opts.repo_root));
 
@@ -2,15 +2,16 @@ use clap::{SubCommand, ArgMatches, Arg};
 
 use error::Error;
 use commands::{BasicOptions, StaticSubcommand, remote, default_explain};
+use commands::select::{self, Selector};
 use libpijul::fs_representation::read_patch;
 use libpijul::DEFAULT_BRANCH;
 use meta::{Meta, Repository};
 use super::ask;
 
 pub fn invocation() -> StaticSubcommand {
-    return SubCommand::with_name("push")
+    let cmd = SubCommand::with_name("push")
         .about("push to a remote repository")
-        .arg(Arg::with_name("remote").help("Repository to push to."))
+        .arg(Arg::with_name("remote").help("Repository to push to, or the name of a remote."))
         .arg(Arg::with_name("repository").help("Local repository."))
         .arg(Arg::with_name("local_branch")
             .long("from-branch")
@@ -18,7 +19,8 @@ pub fn invocation() -> StaticSubcommand {
             .takes_value(true))
         .arg(Arg::with_name("remote_branch")
             .long("to-branch")
-            .help("The branch to push into")
+            .help("The branch to push into. Defaults to the push branch of the remote, \
+                   or to master.")
             .takes_value(true))
         .arg(Arg::with_name("all")
             .short("a")
@@ -26,6 +28,12 @@ pub fn invocation() -> StaticSubcommand {
             .help("Answer 'y' to all questions")
             .takes_value(false))
         .arg(Arg::with_name("set-default").long("set-default"))
+        .arg(Arg::with_name("identity")
+            .short("i")
+            .long("identity")
+            .help("Private key to authenticate to the remote ssh server with. Defaults \
+                   to ~/.ssh/id_ed25519, then to ~/.ssh/id_rsa.")
+            .takes_value(true))
         .arg(Arg::with_name("port")
             .short("p")
             .long("port")
@@ -38,6 +46,7 @@ pub fn invocation() -> StaticSubcommand {
                     Err(_) => Err(val),
                 }
             }));
+    select::add_args(cmd)
 }
 
 #[derive(Debug)]
@@ -46,8 +55,9 @@ pub struct Params<'a> {
     pub yes_to_all: bool,
     pub set_default: bool,
     pub port: Option<u16>,
+    pub identity: Option<&'a str>,
     pub local_branch: &'a str,
-    pub remote_branch: &'a str,
+    pub remote_branch: Option<&'a str>,
 }
 
 fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
@@ -57,8 +67,9 @@ fn parse_args<'a>(args: &'a ArgMatches) -> Params<'a> {
         yes_to_all: args.is_present("all"),
         set_default: args.is_present("set-default"),
         port: args.value_of("port").and_then(|x| Some(x.parse().unwrap())),
+        identity: args.value_of("identity"),
         local_branch: args.value_of("local_branch").unwrap_or(DEFAULT_BRANCH),
-        remote_branch: args.value_of("remote_branch").unwrap_or(DEFAULT_BRANCH),
+        remote_branch: args.value_of("remote_branch"),
     }
 }
 
@@ -67,27 +78,33 @@ pub fn run(arg_matches: &ArgMatches) -> Result<(), Error> {
     let args = parse_args(arg_matches);
     let meta = Meta::load(&opts.repo_root);
     let mut savable = false;
-    let remote = {
+    let repo_root = Some(opts.repo_root.as_path());
+    let (remote, named) = {
         if let Some(remote_id) = args.remote_id {
             savable = true;
-            remote::parse_remote(remote_id, args.port, None)
+            remote::find_remote(&meta, remote_id, args.port, args.identity, None, repo_root)
         } else {
             match meta.pull {
                 Some(Repository::SSH { ref address, ref port }) => {
-                    remote::parse_remote(address, Some(*port), Some(&opts.repo_root))
+                    remote::find_remote(&meta, address, Some(*port), args.identity,
+                                        repo_root, repo_root)
                 }
                 Some(Repository::String(ref host)) => {
-                    remote::parse_remote(host, None, Some(&opts.repo_root))
+                    remote::find_remote(&meta, host, None, args.identity, repo_root, repo_root)
                 }
                 None => return Err(Error::MissingRemoteRepository),
             }
         }
     };
+    let remote_branch = args.remote_branch
+        .or(named.and_then(|n| n.push_branch.as_ref().map(|b| b.as_str())))
+        .unwrap_or(DEFAULT_BRANCH);
     debug!("remote: {:?}", remote);
     let mut session = try!(remote.session());
     let pushable =
-        try!(session.pushable_patches(args.local_branch, args.remote_branch, &opts.repo_root));
-    let pushable = if !args.yes_to_all {
+        try!(session.pushable_patches(args.local_branch, remote_branch, &opts.repo_root));
+    let selector = Selector::from_args(arg_matches)?;
+    let pushable = if selector.is_some() || !args.yes_to_all {
         let mut patches = Vec::new();
         // let patch_dir = patches_dir(r);
         let mut pushable:Vec<_> = pushable.into_iter().collect();
@@ -95,12 +112,19 @@ pub fn run(arg_matches: &ArgMatches) -> Result<(), Error> {
         for &(ref i, _) in pushable.iter() {
             patches.push((i.clone(), read_patch(&opts.repo_root, i.as_ref())?))
         }
-        try!(ask::ask_patches(ask::Command::Push, &patches))
+        if let Some(ref selector) = selector {
+            let repo = opts.open_repo()?;
+            let txn = repo.txn_begin()?;
+            let branch = txn.get_branch(args.local_branch);
+            selector.select(&txn, branch.as_ref(), &patches)
+        } else {
+            try!(ask::ask_patches(ask::Command::Push, &patches))
+        }
     } else {
         pushable.into_iter().map(|(h, _)| h).collect()
     };
 
-    try!(session.push(&opts.repo_root, args.remote_branch, &pushable));
+    try!(session.push(&opts.repo_root, remote_branch, &pushable));
     if args.set_default && savable {
         if let Some(remote_id) = args.remote_id {
             let push = if let Some(p) = args.port {
@@ -3,11 +3,12 @@ use chrono;
 use commands::{BasicOptions, StaticSubcommand, default_explain};
 use libpijul::{Repository, Hash, InodeUpdate, Patch};
 use libpijul::fs_representation::pristine_dir;
+use libpijul::signature::sign_patch;
 use std::mem::drop;
 use error::Error;
 
 use std::path::Path;
-use meta::{GlobalMeta, Meta};
+use meta::{GlobalMeta, Meta, KeyStore};
 use commands::ask;
 use rustc_serialize::base64::{ToBase64, URL_SAFE};
 use super::ask::{ChangesDirection, ask_changes};
@@ -40,6 +41,17 @@ pub fn invocation() -> StaticSubcommand {
              .long("author")
              .help("Author of this patch (multiple occurrences allowed)")
              .multiple(true)
+             .takes_value(true))
+        .arg(Arg::with_name("sign")
+             .long("sign")
+             .help("Sign the patch with the key generated by `pijul key generate`")
+             .takes_value(false))
+        .arg(Arg::with_name("full-check")
+             .long("full-check")
+             .help("Read all files, including those that look unchanged since the last check."))
+        .arg(Arg::with_name("prefix")
+             .help("Only record changes in these files or directories.")
+             .multiple(true)
              .takes_value(true));
 }
 
@@ -49,6 +61,16 @@ pub fn run(args: &ArgMatches) -> Result<Option<Hash>, Error> {
     let patch_name_arg = args.value_of("message");
     let authors_arg = args.values_of("author").map(|x| x.collect::<Vec<_>>());
     let branch_name = opts.branch();
+    let signing_key = if args.is_present("sign") {
+        Some(KeyStore::load()?.signing_key()?)
+    } else {
+        None
+    };
+
+    let prefixes = match args.values_of("prefix") {
+        Some(p) => p.map(|p| opts.relative_path(p)).collect::<Result<Vec<_>, _>>()?,
+        None => Vec::new(),
+    };
 
     let (changes, syncs) = {
         // Increase by 100 pages. The most things record can
@@ -56,7 +78,10 @@ pub fn run(args: &ArgMatches) -> Result<Option<Hash>, Error> {
         // at most O(log n) blocks.
         let repo = opts.open_and_grow_repo(409600)?;
         let mut txn = repo.mut_txn_begin(rand::thread_rng())?;
-        let (changes, syncs) = txn.record(&branch_name, &opts.repo_root)?;
+        let full_check = args.is_present("full-check");
+        let defaults = Meta::load(&opts.repo_root).file_attributes();
+        let (changes, syncs) =
+            txn.record(&branch_name, &opts.repo_root, &prefixes, full_check, &defaults)?;
         if !yes_to_all {
             let c = try!(ask_changes(&txn, &changes, ChangesDirection::Record));
             let selected = changes.into_iter()
@@ -126,13 +151,19 @@ pub fn run(args: &ArgMatches) -> Result<Option<Hash>, Error> {
         loop {
             match record_no_resize(&pristine, &opts.repo_root, &branch_name, &patch, &syncs, increase) {
                 Err(ref e) if e.lacks_space() => { increase *= 2 },
+                Ok(Some(hash)) => {
+                    if let Some(ref key) = signing_key {
+                        sign_patch(&opts.repo_root, hash.as_ref(), key)?;
+                    }
+                    return Ok(Some(hash))
+                }
                 e => return e
             }
         }
     }
 }
 
-fn record_no_resize(pristine_dir: &Path, r: &Path, branch_name: &str, patch: &Patch, syncs: &[InodeUpdate], increase: u64) -> Result<Option<Hash>, Error> {
+pub fn record_no_resize(pristine_dir: &Path, r: &Path, branch_name: &str, patch: &Patch, syncs: &[InodeUpdate], increase: u64) -> Result<Option<Hash>, Error> {
 
     let size_increase = increase + patch.size_upper_bound() as u64;
     let repo = try!(Repository::open(&pristine_dir, Some(size_increase)).map_err(Error::Repository));
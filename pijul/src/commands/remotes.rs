@@ -0,0 +1,221 @@
+use clap::{SubCommand, ArgMatches, Arg};
+use commands::{BasicOptions, StaticSubcommand, default_explain};
+use commands::remote::{find_remote, known_patches};
+use libpijul::{Hash, DEFAULT_BRANCH};
+use meta::{GlobalMeta, Meta, NamedRemote};
+use std::collections::{BTreeMap, HashSet};
+use error::Error;
+
+pub fn invocation() -> StaticSubcommand {
+    return SubCommand::with_name("remote")
+        .about("Manage the named remote repositories")
+        .arg(Arg::with_name("repository")
+             .long("repository")
+             .help("Path to a pijul repository. Defaults to the repository containing the \
+                    current directory.")
+             .takes_value(true))
+        .arg(Arg::with_name("global")
+             .long("global")
+             .help("Use the remotes of the global configuration, available in all \
+                    repositories and to pijul clone, instead of the remotes of the \
+                    repository."))
+        .subcommand(SubCommand::with_name("add")
+                    .about("Add a named remote")
+                    .arg(Arg::with_name("name")
+                         .help("Name of the remote, usable instead of its address in pull, \
+                                push and clone.")
+                         .required(true))
+                    .arg(Arg::with_name("address")
+                         .help("Address of the repository.")
+                         .required(true))
+                    .arg(Arg::with_name("port")
+                         .short("p")
+                         .long("port")
+                         .help("Port of the remote ssh server.")
+                         .takes_value(true)
+                         .validator(|val| {
+                             let x: Result<u16, _> = val.parse();
+                             match x {
+                                 Ok(_) => Ok(()),
+                                 Err(_) => Err(val),
+                             }
+                         }))
+                    .arg(Arg::with_name("pull-branch")
+                         .long("pull-branch")
+                         .help("The branch to pull from when --from-branch is not given.")
+                         .takes_value(true))
+                    .arg(Arg::with_name("push-branch")
+                         .long("push-branch")
+                         .help("The branch to push to when --to-branch is not given.")
+                         .takes_value(true))
+                    .arg(Arg::with_name("identity")
+                         .short("i")
+                         .long("identity")
+                         .help("Private key to authenticate to the remote ssh server with.")
+                         .takes_value(true)))
+        .subcommand(SubCommand::with_name("list")
+                    .about("List the named remotes"))
+        .subcommand(SubCommand::with_name("status")
+                    .about("Compare the current branch with what remotes had on the last pull \
+                            or push")
+                    .arg(Arg::with_name("remote")
+                         .help("Name or address of the remote. Defaults to all named \
+                                remotes."))
+                    .arg(Arg::with_name("branch")
+                         .long("branch")
+                         .help("The local branch. Defaults to the current branch.")
+                         .takes_value(true))
+                    .arg(Arg::with_name("remote_branch")
+                         .long("remote-branch")
+                         .help("The remote branch. Defaults to the pull branch of the remote, \
+                                or to master.")
+                         .takes_value(true))
+                    .arg(Arg::with_name("fetch")
+                         .long("fetch")
+                         .help("Download the list of patches of the remotes first.")))
+        .subcommand(SubCommand::with_name("remove")
+                    .about("Remove a named remote")
+                    .arg(Arg::with_name("name")
+                         .help("Name of the remote.")
+                         .required(true)))
+        .subcommand(SubCommand::with_name("rename")
+                    .about("Rename a named remote")
+                    .arg(Arg::with_name("name")
+                         .help("Current name of the remote.")
+                         .required(true))
+                    .arg(Arg::with_name("new_name")
+                         .help("New name of the remote.")
+                         .required(true)))
+}
+
+fn print_remote(name: &str, remote: &NamedRemote) {
+    print!("{} {}", name, remote.address);
+    if let Some(port) = remote.port {
+        print!(" port={}", port)
+    }
+    if let Some(ref branch) = remote.pull_branch {
+        print!(" pull-branch={}", branch)
+    }
+    if let Some(ref branch) = remote.push_branch {
+        print!(" push-branch={}", branch)
+    }
+    if let Some(ref identity) = remote.identity_file {
+        print!(" identity={}", identity)
+    }
+    println!("")
+}
+
+fn status(opts: &BasicOptions, args: &ArgMatches) -> Result<(), Error> {
+    let meta = Meta::load(&opts.repo_root);
+    let local_branch = args.value_of("branch").map(|b| b.to_string()).unwrap_or(opts.branch());
+    let local: HashSet<Hash> = {
+        let repo = opts.open_repo()?;
+        let txn = repo.txn_begin()?;
+        match txn.get_branch(&local_branch) {
+            Some(branch) => {
+                txn.iter_patches(&branch, None)
+                    .map(|(hash, _)| txn.get_external(&hash).unwrap().to_owned())
+                    .collect()
+            }
+            None => HashSet::new(),
+        }
+    };
+    let remotes: Vec<&str> = match args.value_of("remote") {
+        Some(remote) => vec![remote],
+        None => meta.remotes.keys().map(|name| name.as_str()).collect(),
+    };
+    for remote_id in remotes {
+        let (remote, named) = find_remote(&meta, remote_id, None, None, None, Some(&opts.repo_root));
+        let remote_branch = args.value_of("remote_branch")
+            .or(named.and_then(|n| n.pull_branch.as_ref().map(|b| b.as_str())))
+            .unwrap_or(DEFAULT_BRANCH);
+        if args.is_present("fetch") {
+            remote.session()?.fetch_changes(&opts.repo_root, remote_branch)?;
+        }
+        match known_patches(&opts.repo_root, &remote.tracking_id(), remote_branch)? {
+            Some(known) => {
+                let known: HashSet<Hash> = known.into_iter().map(|(h, _)| h).collect();
+                let ahead = local.difference(&known).count();
+                let behind = known.difference(&local).count();
+                if ahead == 0 && behind == 0 {
+                    println!("{} ({}): up to date", remote_id, remote_branch)
+                } else {
+                    println!("{} ({}): {} ahead, {} behind", remote_id, remote_branch, ahead, behind)
+                }
+            }
+            None => println!("{} ({}): never pulled from or pushed to", remote_id, remote_branch),
+        }
+    }
+    Ok(())
+}
+
+pub fn run(args: &ArgMatches) -> Result<(), Error> {
+    if let ("status", Some(status_args)) = args.subcommand() {
+        let opts = BasicOptions::from_args(args)?;
+        return status(&opts, status_args)
+    }
+    let global = args.is_present("global");
+    let opts = if global {
+        None
+    } else {
+        Some(BasicOptions::from_args(args)?)
+    };
+    let mut remotes = match opts {
+        Some(ref opts) => Meta::load_remotes(&opts.repo_root),
+        None => GlobalMeta::load_remotes()?,
+    };
+    match args.subcommand() {
+        ("add", Some(args)) => {
+            let name = args.value_of("name").unwrap();
+            if remotes.contains_key(name) {
+                return Err(Error::RemoteExists(name.to_string()))
+            }
+            remotes.insert(name.to_string(),
+                           NamedRemote {
+                               address: args.value_of("address").unwrap().to_string(),
+                               port: args.value_of("port").map(|p| p.parse().unwrap()),
+                               pull_branch: args.value_of("pull-branch").map(|b| b.to_string()),
+                               push_branch: args.value_of("push-branch").map(|b| b.to_string()),
+                               identity_file: args.value_of("identity").map(|i| i.to_string()),
+                           });
+        }
+        ("remove", Some(args)) => {
+            let name = args.value_of("name").unwrap();
+            if remotes.remove(name).is_none() {
+                return Err(Error::NoSuchRemote(name.to_string()))
+            }
+        }
+        ("rename", Some(args)) => {
+            let name = args.value_of("name").unwrap();
+            let new_name = args.value_of("new_name").unwrap();
+            if remotes.contains_key(new_name) {
+                return Err(Error::RemoteExists(new_name.to_string()))
+            }
+            match remotes.remove(name) {
+                Some(remote) => {
+                    remotes.insert(new_name.to_string(), remote);
+                }
+                None => return Err(Error::NoSuchRemote(name.to_string())),
+            }
+        }
+        _ => {
+            // In a repository, the global remotes are usable too.
+            let all: BTreeMap<_, _> = match opts {
+                Some(ref opts) => Meta::load(&opts.repo_root).remotes,
+                None => remotes,
+            };
+            for (name, remote) in all.iter() {
+                print_remote(name, remote)
+            }
+            return Ok(())
+        }
+    }
+    match opts {
+        Some(ref opts) => Meta::save_remotes(&opts.repo_root, remotes),
+        None => GlobalMeta::save_remotes(remotes),
+    }
+}
+
+pub fn explain(res: Result<(), Error>) {
+    default_explain(res)
+}
@@ -0,0 +1,205 @@
+use clap::{SubCommand, ArgMatches, Arg};
+use chrono;
+use commands::{BasicOptions, StaticSubcommand, default_explain};
+use commands::ask;
+use commands::record::record_no_resize;
+use libpijul::{Hash, MutTxn, PatchId};
+use libpijul::attributes::Attributes;
+use libpijul::conflict::Conflict;
+use libpijul::fs_representation::pristine_dir;
+use meta::Meta;
+use rand;
+use rustc_serialize::base64::{ToBase64, URL_SAFE};
+use std::fs::{File, canonicalize};
+use std::io::{Read, Write};
+use std::mem::drop;
+use std::path::Path;
+use error::Error;
+
+pub fn invocation() -> StaticSubcommand {
+    return SubCommand::with_name("resolve")
+        .about("Resolve the conflicts of a file by choosing sides, and record the result")
+        .arg(Arg::with_name("repository")
+             .long("repository")
+             .help("Path to a pijul repository. Defaults to the repository containing the \
+                    current directory.")
+             .takes_value(true))
+        .arg(Arg::with_name("branch")
+             .long("branch")
+             .help("The branch where to record the resolution. Defaults to the current branch.")
+             .takes_value(true))
+        .arg(Arg::with_name("file")
+             .help("The conflicted file.")
+             .required(true)
+             .takes_value(true))
+        .arg(Arg::with_name("take")
+             .long("take")
+             .help("Resolve all conflicts by keeping this side, given either as a number \
+                    (starting from 1) or as the hash of a patch. Without this option, \
+                    sides are chosen interactively for each conflict.")
+             .takes_value(true))
+        .arg(Arg::with_name("message")
+             .short("m")
+             .long("name")
+             .help("The name of the patch to record")
+             .takes_value(true))
+        .arg(Arg::with_name("author")
+             .short("A")
+             .long("author")
+             .help("Author of this patch (multiple occurrences allowed)")
+             .multiple(true)
+             .takes_value(true))
+}
+
+/// The side to take in each conflict, as given by `--take`.
+enum Take {
+    Side(usize),
+    Patch(PatchId),
+}
+
+impl Take {
+    fn choose(&self, conflict: &Conflict) -> Option<usize> {
+        match *self {
+            Take::Side(n) if n < conflict.sides.len() => Some(n),
+            Take::Side(_) => None,
+            Take::Patch(ref p) => conflict.sides.iter().position(|s| s.patches().contains(p)),
+        }
+    }
+}
+
+fn parse_take<R: rand::Rng>(txn: &MutTxn<R>, repo_root: &Path, take: &str) -> Result<Take, Error> {
+    if let Ok(n) = take.parse::<usize>() {
+        if n >= 1 {
+            return Ok(Take::Side(n - 1))
+        }
+    }
+    match Hash::from_base64(take) {
+        Some(hash) => {
+            match txn.get_internal(hash.as_ref()) {
+                Some(internal) => Ok(Take::Patch(internal.to_owned())),
+                None => Err(Error::PatchNotFound(repo_root.to_string_lossy().into_owned(), hash)),
+            }
+        }
+        None => Err(Error::WrongHash),
+    }
+}
+
+fn print_conflict<R: rand::Rng>(txn: &MutTxn<R>, n: usize, conflict: &Conflict) {
+    println!("Conflict {}, line {}:", n + 1, conflict.line);
+    for (i, side) in conflict.sides.iter().enumerate() {
+        let patches: Vec<_> = side.patches()
+            .iter()
+            .filter_map(|p| txn.get_external(p).map(|h| h.to_base64(URL_SAFE)))
+            .collect();
+        println!("Side {} (from {}):", i + 1, patches.join(", "));
+        for line in side.lines.iter() {
+            print!("  {}", String::from_utf8_lossy(&line.contents));
+            if line.contents.last() != Some(&b'\n') {
+                println!("")
+            }
+        }
+    }
+}
+
+pub fn run(args: &ArgMatches) -> Result<Option<Hash>, Error> {
+    let opts = BasicOptions::from_args(args)?;
+    let branch_name = opts.branch();
+    let file = Path::new(args.value_of("file").unwrap());
+    let path = canonicalize(opts.cwd.join(file))?;
+    let file = match path.strip_prefix(&opts.repo_root) {
+        Ok(file) => file.to_path_buf(),
+        Err(_) => return Err(Error::InvalidPath(file.to_string_lossy().into_owned())),
+    };
+    let defaults = Meta::load(&opts.repo_root).file_attributes();
+    let attributes = Attributes::load(&opts.repo_root, &defaults)?.get(&file);
+
+    let (changes, mut contents) = {
+        let repo = opts.open_and_grow_repo(409600)?;
+        let txn = repo.mut_txn_begin(rand::thread_rng())?;
+        let branch = match txn.get_branch(&branch_name) {
+            Some(branch) => branch,
+            None => return Err(Error::NoSuchBranch),
+        };
+        let inode = txn.find_inode(&file)?;
+        if txn.is_directory(&inode) {
+            return Err(Error::IsDirectory)
+        }
+        let key = txn.get_inodes(&inode).unwrap().key.clone();
+
+        // Refuse to overwrite unrecorded changes to the file.
+        let mut recorded = Vec::new();
+        {
+            let mut graph = txn.retrieve(&branch, &key);
+            txn.output_file_contents(&mut graph, &attributes, &mut recorded, &mut Vec::new())?;
+        }
+        let mut current = Vec::new();
+        File::open(&path)?.read_to_end(&mut current)?;
+        attributes.normalize_eol(&mut current);
+        if current != recorded {
+            return Err(Error::ModifiedConflictedFile(file))
+        }
+
+        let conflicts = txn.file_conflicts(&branch, &key)?;
+        if conflicts.is_empty() {
+            println!("No conflicts in {}", file.display());
+            return Ok(None)
+        }
+        let choices: Vec<Option<usize>> = if let Some(take) = args.value_of("take") {
+            let take = parse_take(&txn, &opts.repo_root, take)?;
+            conflicts.iter().map(|c| take.choose(c)).collect()
+        } else {
+            let mut choices = Vec::new();
+            for (n, conflict) in conflicts.iter().enumerate() {
+                print_conflict(&txn, n, conflict);
+                choices.push(ask::ask_conflict_side(conflict.sides.len())?)
+            }
+            choices
+        };
+        txn.resolve_conflicts(&branch, &file, &choices, attributes.diff.unwrap_or_default())?
+    };
+
+    if changes.is_empty() {
+        println!("No conflict resolved");
+        return Ok(None)
+    }
+
+    let repo = opts.open_repo()?;
+    let patch = {
+        let txn = repo.txn_begin()?;
+        let meta = Meta::load(&opts.repo_root);
+        let authors: Vec<String> = if let Some(authors) = args.values_of("author") {
+            authors.map(|x| x.to_string()).collect()
+        } else if meta.default_authors.len() > 0 {
+            meta.default_authors.clone()
+        } else {
+            ask::ask_authors()?
+        };
+        let patch_name = if let Some(m) = args.value_of("message") {
+            m.to_string()
+        } else {
+            format!("Resolve conflicts in {}", file.display())
+        };
+        let changes = changes.into_iter().flat_map(|x| x.into_iter()).collect();
+        let branch = txn.get_branch(&branch_name).unwrap();
+        txn.new_patch(&branch, authors, patch_name, None, chrono::UTC::now(), changes)
+    };
+    drop(repo);
+
+    let mut increase = 409600;
+    let pristine = pristine_dir(&opts.repo_root);
+    loop {
+        match record_no_resize(&pristine, &opts.repo_root, &branch_name, &patch, &[], increase) {
+            Err(ref e) if e.lacks_space() => increase *= 2,
+            Ok(hash) => {
+                attributes.convert_eol(&mut contents);
+                File::create(&path)?.write_all(&contents)?;
+                return Ok(hash)
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub fn explain(res: Result<Option<Hash>, Error>) {
+    default_explain(res)
+}
@@ -3,10 +3,12 @@ use chrono;
 use commands::{StaticSubcommand, default_explain};
 use libpijul::{Repository, InodeUpdate};
 use libpijul::patch::{Record, Patch};
+use libpijul::attributes::FileAttributes;
 use libpijul::fs_representation::pristine_dir;
 use std::path::Path;
 use rand;
 use error;
+use meta::Meta;
 use super::BasicOptions;
 use super::ask::{ChangesDirection, ask_changes};
 
@@ -33,6 +35,7 @@ pub fn run(args: &ArgMatches) -> Result<(), error::Error> {
     let opts = BasicOptions::from_args(args)?;
     let yes_to_all = args.is_present("all");
     let branch_name = opts.branch();
+    let defaults = Meta::load(&opts.repo_root).file_attributes();
 
     // Generate the pending patch.
     let (pending, pending_syncs):(_,Vec<_>) =
@@ -40,7 +43,8 @@ pub fn run(args: &ArgMatches) -> Result<(), error::Error> {
             let repo = opts.open_and_grow_repo(409600)?;
             let mut txn = repo.mut_txn_begin(rand::thread_rng())?;
             let (changes, syncs):(Vec<Record>, _) = {
-                let (changes, syncs) = txn.record(&branch_name, &opts.repo_root)?;
+                let (changes, syncs) =
+                    txn.record(&branch_name, &opts.repo_root, &[], false, &defaults)?;
                 let c = try!(ask_changes(&txn, &changes, ChangesDirection::Revert));
                 let selected = changes.into_iter()
                     .enumerate()
@@ -61,7 +65,7 @@ pub fn run(args: &ArgMatches) -> Result<(), error::Error> {
     let mut size_increase = None;
     let pristine = pristine_dir(&opts.repo_root);
     loop {
-        match output_repository(&opts.repo_root, &pristine, &branch_name, size_increase, &pending, &pending_syncs) {
+        match output_repository(&opts.repo_root, &pristine, &branch_name, size_increase, &pending, &pending_syncs, &defaults) {
             Err(ref e) if e.lacks_space() => {
                 size_increase = Some(Repository::repository_size(&pristine).unwrap())
             },
@@ -70,10 +74,10 @@ pub fn run(args: &ArgMatches) -> Result<(), error::Error> {
     }
 }
 
-fn output_repository(r: &Path, pristine_dir: &Path, branch: &str, size_increase: Option<u64>, pending: &Patch, pending_syncs: &[InodeUpdate]) -> Result<(), error::Error> {
+fn output_repository(r: &Path, pristine_dir: &Path, branch: &str, size_increase: Option<u64>, pending: &Patch, pending_syncs: &[InodeUpdate], defaults: &FileAttributes) -> Result<(), error::Error> {
     let repo = try!(Repository::open(&pristine_dir, size_increase));
     let mut txn = try!(repo.mut_txn_begin(rand::thread_rng()));
-    try!(txn.output_repository(&branch, &r, pending, pending_syncs));
+    try!(txn.output_repository(&branch, &r, pending, pending_syncs, defaults));
     txn.commit()?;
     Ok(())
 }
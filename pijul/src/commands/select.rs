@@ -0,0 +1,127 @@
+//! Non-interactive selection of the patches to push or pull.
+
+use clap::{ArgMatches, Arg};
+use commands::StaticSubcommand;
+use error::Error;
+use libpijul::{Branch, Hash, Txn};
+use libpijul::patch::Patch;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+/// Adds the selection options to a command.
+pub fn add_args(cmd: StaticSubcommand) -> StaticSubcommand {
+    cmd.arg(Arg::with_name("path")
+            .long("path")
+            .help("Only select patches touching this file or directory, relative to the \
+                   root of the repository (multiple occurrences allowed).")
+            .takes_value(true)
+            .multiple(true))
+        .arg(Arg::with_name("author")
+            .long("author")
+            .help("Only select patches with an author containing this string.")
+            .takes_value(true))
+        .arg(Arg::with_name("name")
+            .long("name")
+            .help("Only select patches whose name matches this regular expression.")
+            .takes_value(true))
+        .arg(Arg::with_name("patch")
+            .long("patch")
+            .help("Select this patch, in addition to the patches matching the other \
+                   options (multiple occurrences allowed).")
+            .takes_value(true)
+            .multiple(true))
+}
+
+/// Patches to select, as given on the command line. The
+/// dependencies of the selected patches are always selected.
+pub struct Selector {
+    paths: Vec<PathBuf>,
+    author: Option<String>,
+    name: Option<Regex>,
+    hashes: Vec<Hash>,
+}
+
+impl Selector {
+    /// Reads the selection options, returning `None` if there are
+    /// none, in which case patches should be selected interactively
+    /// or all at once.
+    pub fn from_args(args: &ArgMatches) -> Result<Option<Selector>, Error> {
+        let mut hashes = Vec::new();
+        if let Some(h) = args.values_of("patch") {
+            for h in h {
+                match Hash::from_base64(h) {
+                    Some(h) => hashes.push(h),
+                    None => return Err(Error::WrongHash),
+                }
+            }
+        }
+        let selector = Selector {
+            paths: args.values_of("path")
+                .map(|p| p.map(|p| PathBuf::from(p.trim_matches('/'))).collect())
+                .unwrap_or(Vec::new()),
+            author: args.value_of("author").map(|a| a.to_string()),
+            name: match args.value_of("name") {
+                Some(r) => Some(Regex::new(r)?),
+                None => None,
+            },
+            hashes: hashes,
+        };
+        if selector.paths.is_empty() && selector.author.is_none() && selector.name.is_none() &&
+            selector.hashes.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(selector))
+        }
+    }
+
+    fn has_filters(&self) -> bool {
+        !self.paths.is_empty() || self.author.is_some() || self.name.is_some()
+    }
+
+    /// Selects patches among `patches`, given in the order in which
+    /// they are applied. Paths are looked up in `branch` of the local
+    /// repository, and then in the files added by `patches`.
+    pub fn select(&self,
+                  txn: &Txn,
+                  branch: Option<&Branch>,
+                  patches: &[(Hash, Patch)])
+                  -> HashSet<Hash> {
+        let mut path_keys: Vec<_> = self.paths
+            .iter()
+            .map(|path| txn.path_history_keys(branch, path, patches))
+            .collect();
+        let mut selected = HashSet::new();
+        for &(ref hash, ref patch) in patches {
+            // Always update the keys of all paths, so that the nodes
+            // added by this patch are known to the next ones.
+            let mut touches = path_keys.is_empty();
+            for keys in path_keys.iter_mut() {
+                touches |= patch.touches_keys(hash, keys)
+            }
+            let matches = self.has_filters() && touches &&
+                self.author
+                    .as_ref()
+                    .map(|a| patch.header.authors.iter().any(|x| x.contains(a.as_str())))
+                    .unwrap_or(true) &&
+                self.name.as_ref().map(|n| n.is_match(&patch.header.name)).unwrap_or(true);
+            if matches || self.hashes.contains(hash) {
+                selected.insert(hash.clone());
+            }
+        }
+
+        // Close over the dependencies that are among `patches`.
+        let by_hash: HashMap<&Hash, &Patch> = patches.iter().map(|&(ref h, ref p)| (h, p)).collect();
+        let mut stack: Vec<Hash> = selected.iter().cloned().collect();
+        while let Some(hash) = stack.pop() {
+            if let Some(patch) = by_hash.get(&hash) {
+                for dep in patch.dependencies.iter() {
+                    if by_hash.contains_key(dep) && selected.insert(dep.clone()) {
+                        stack.push(dep.clone())
+                    }
+                }
+            }
+        }
+        selected
+    }
+}
@@ -0,0 +1,297 @@
+use clap::{SubCommand, ArgMatches, Arg};
+use commands::{BasicOptions, StaticSubcommand, default_explain};
+use commands::remote::{HTTP_APPLY_PATH, HTTP_APPLIED_AFTER_PARAM, write_changes_after};
+use error::Error;
+use hyper::header::{Authorization, Bearer};
+use hyper::method::Method;
+use hyper::server::{Handler, Request, Response, Server};
+use hyper::status::StatusCode;
+use hyper::uri::RequestUri;
+use libpijul::{ApplyTimestamp, Hash, Repository, apply_resize};
+use libpijul::fs_representation::{pristine_dir, patches_dir, patch_file_name, PIJUL_DIR_NAME,
+                                  PATCHES_DIR_NAME};
+use libpijul::patch::Patch;
+use meta::{Meta, KeyStore};
+use rustc_serialize::base64::{FromBase64, ToBase64, URL_SAFE};
+use std::collections::HashSet;
+use std::fs::{File, metadata, rename};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub fn invocation() -> StaticSubcommand {
+    return SubCommand::with_name("serve")
+        .about("Serve a repository over HTTP")
+        .arg(Arg::with_name("repository")
+             .long("repository")
+             .help("Path to a pijul repository. Defaults to the repository containing the \
+                    current directory.")
+             .takes_value(true))
+        .arg(Arg::with_name("address")
+             .long("address")
+             .help("Address to listen on. Defaults to 127.0.0.1:8080.")
+             .takes_value(true))
+        .arg(Arg::with_name("token-file")
+             .long("token-file")
+             .help("File containing the tokens allowed to push, one per line. Without this \
+                    option, the repository is read-only. Clients send their token in the \
+                    PIJUL_HTTP_TOKEN environment variable.")
+             .takes_value(true))
+        .arg(Arg::with_name("max-body-size")
+             .long("max-body-size")
+             .help("Largest request body accepted, in bytes. Defaults to 64 MiB.")
+             .takes_value(true)
+             .validator(|val| {
+                 match val.parse::<u64>() {
+                     Ok(_) => Ok(()),
+                     Err(_) => Err(val),
+                 }
+             }))
+}
+
+/// Default value of `--max-body-size`.
+const DEFAULT_MAX_BODY_SIZE: u64 = 64 << 20;
+
+struct Serve {
+    repo_root: PathBuf,
+    tokens: Vec<String>,
+    /// Requests with larger bodies are refused.
+    max_body_size: u64,
+    /// Applying patches needs a mutable transaction, so only one
+    /// push is handled at a time.
+    apply_lock: Mutex<()>,
+}
+
+/// A response to a request: a status code and a body.
+type Reply = (StatusCode, Vec<u8>);
+
+fn not_found() -> Reply {
+    (StatusCode::NotFound, b"Not found\n".to_vec())
+}
+
+fn payload_too_large() -> Reply {
+    (StatusCode::PayloadTooLarge, b"Payload too large\n".to_vec())
+}
+
+/// Compares two strings in a time that depends only on their
+/// lengths, so that tokens cannot be guessed by timing.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() &&
+        a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl Serve {
+    fn is_authorized(&self, req: &Request) -> bool {
+        match req.headers.get::<Authorization<Bearer>>() {
+            Some(&Authorization(Bearer { ref token })) => {
+                // Compare with all tokens, without stopping at the first match.
+                self.tokens.iter().fold(false, |found, t| constant_time_eq(t, token) | found)
+            }
+            None => false,
+        }
+    }
+
+    /// Reads the body of `req`, or returns `None` if it is larger
+    /// than `max_body_size`.
+    fn read_body(&self, req: &mut Request) -> Result<Option<Vec<u8>>, Error> {
+        let mut body = Vec::new();
+        req.take(self.max_body_size + 1).read_to_end(&mut body)?;
+        if body.len() as u64 > self.max_body_size {
+            Ok(None)
+        } else {
+            Ok(Some(body))
+        }
+    }
+
+    /// The list of patches applied to a branch, in the format of
+    /// `pijul changes --hash-only`, and with `--applied-after` if
+    /// `after` is given.
+    fn changes(&self, branch_base64: &str, after: Option<ApplyTimestamp>) -> Result<Reply, Error> {
+        let branch_name = match branch_base64.from_base64().ok().and_then(|b| String::from_utf8(b).ok()) {
+            Some(name) => name,
+            None => return Ok(not_found()),
+        };
+        let repo = Repository::open(&pristine_dir(&self.repo_root), None)?;
+        let txn = repo.txn_begin()?;
+        let branch = match txn.get_branch(&branch_name) {
+            Some(branch) => branch,
+            None => return Ok(not_found()),
+        };
+        let mut body = Vec::new();
+        if let Some(after) = after {
+            write_changes_after(&mut body, &txn, &branch, after)?
+        } else {
+            for (s, hash) in txn.iter_applied(&branch, None) {
+                let hash_ext = txn.get_external(&hash).unwrap();
+                writeln!(body, "{}:{}", hash_ext.to_base64(URL_SAFE), s)?;
+            }
+        }
+        Ok((StatusCode::Ok, body))
+    }
+
+    fn download_patch(&self, hash: &Hash) -> Result<Reply, Error> {
+        let path = patches_dir(&self.repo_root).join(&patch_file_name(hash.as_ref()));
+        let mut body = Vec::new();
+        match File::open(&path) {
+            Ok(mut f) => {
+                f.read_to_end(&mut body)?;
+                Ok((StatusCode::Ok, body))
+            }
+            Err(_) => Ok(not_found()),
+        }
+    }
+
+    /// Saves an uploaded patch, after checking that its contents
+    /// match its hash.
+    fn upload_patch(&self, hash: &Hash, req: &mut Request) -> Result<Reply, Error> {
+        let body = match self.read_body(req)? {
+            Some(body) => body,
+            None => return Ok(payload_too_large()),
+        };
+        match Patch::from_reader_compressed(&mut &body[..]) {
+            Ok((ref h, _, _)) if h == hash => {}
+            _ => return Ok((StatusCode::BadRequest, b"Invalid patch\n".to_vec())),
+        }
+        let path = patches_dir(&self.repo_root).join(&patch_file_name(hash.as_ref()));
+        if metadata(&path).is_err() {
+            let mut tmp = path.clone();
+            tmp.set_extension("tmp");
+            File::create(&tmp)?.write_all(&body)?;
+            rename(&tmp, &path)?;
+        }
+        Ok((StatusCode::Ok, Vec::new()))
+    }
+
+    /// Applies patches uploaded before, whose hashes are given one
+    /// per line, to a branch.
+    fn apply(&self, branch_base64: &str, req: &mut Request) -> Result<Reply, Error> {
+        let branch_name = match branch_base64.from_base64().ok().and_then(|b| String::from_utf8(b).ok()) {
+            Some(name) => name,
+            None => return Ok(not_found()),
+        };
+        let body = match self.read_body(req)? {
+            Some(body) => body,
+            None => return Ok(payload_too_large()),
+        };
+        let body = match String::from_utf8(body) {
+            Ok(body) => body,
+            Err(_) => return Ok((StatusCode::BadRequest, b"Invalid hash\n".to_vec())),
+        };
+        let mut hashes = HashSet::new();
+        for line in body.lines().filter(|l| !l.is_empty()) {
+            match Hash::from_base64(line) {
+                Some(hash) => {
+                    let path = patches_dir(&self.repo_root).join(&patch_file_name(hash.as_ref()));
+                    if metadata(&path).is_err() {
+                        return Ok((StatusCode::BadRequest,
+                                   format!("Missing patch {}\n", line).into_bytes()))
+                    }
+                    hashes.insert(hash);
+                }
+                None => return Ok((StatusCode::BadRequest, b"Invalid hash\n".to_vec())),
+            }
+        }
+        let _lock = self.apply_lock.lock().unwrap();
+        let meta = Meta::load(&self.repo_root);
+        KeyStore::load()?.check_signatures(&self.repo_root, hashes.iter(), meta.signature_policy)?;
+        let defaults = meta.file_attributes();
+        loop {
+            match apply_resize(&self.repo_root, &branch_name, hashes.iter(), &defaults) {
+                Err(ref e) if e.lacks_space() => {}
+                Ok(()) => return Ok((StatusCode::Ok, Vec::new())),
+                Err(e) => return Err(From::from(e)),
+            }
+        }
+    }
+
+    fn reply(&self, req: &mut Request) -> Result<Reply, Error> {
+        let (path, query) = match req.uri {
+            RequestUri::AbsolutePath(ref path) => {
+                let mut it = path.splitn(2, '?');
+                (it.next().unwrap().to_string(), it.next().unwrap_or("").to_string())
+            }
+            _ => return Ok(not_found()),
+        };
+        let c: Vec<&str> = path.trim_matches('/').split('/').collect();
+        if c.len() < 2 || c[0] != PIJUL_DIR_NAME {
+            return Ok(not_found())
+        }
+        let method = req.method.clone();
+        if c.len() == 2 && c[1].starts_with("changes.") && method == Method::Get {
+            let after = query.split('&')
+                .filter_map(|param| {
+                    let mut it = param.splitn(2, '=');
+                    match (it.next(), it.next()) {
+                        (Some(HTTP_APPLIED_AFTER_PARAM), Some(v)) => v.parse().ok(),
+                        _ => None,
+                    }
+                })
+                .next();
+            self.changes(&c[1]["changes.".len()..], after)
+
+        } else if c.len() == 3 && c[1] == PATCHES_DIR_NAME && c[2].ends_with(".gz") {
+            let hash = match Hash::from_base64(&c[2][..c[2].len() - 3]) {
+                Some(hash) => hash,
+                None => return Ok(not_found()),
+            };
+            match method {
+                Method::Get => self.download_patch(&hash),
+                Method::Put if self.is_authorized(req) => self.upload_patch(&hash, req),
+                Method::Put => Ok((StatusCode::Unauthorized, b"Unauthorized\n".to_vec())),
+                _ => Ok((StatusCode::MethodNotAllowed, Vec::new())),
+            }
+
+        } else if c.len() == 3 && c[1] == HTTP_APPLY_PATH && method == Method::Post {
+            if self.is_authorized(req) {
+                self.apply(c[2], req)
+            } else {
+                Ok((StatusCode::Unauthorized, b"Unauthorized\n".to_vec()))
+            }
+
+        } else {
+            Ok(not_found())
+        }
+    }
+}
+
+impl Handler for Serve {
+    fn handle(&self, mut req: Request, mut res: Response) {
+        let (status, body) = match self.reply(&mut req) {
+            Ok(reply) => reply,
+            Err(e) => (StatusCode::InternalServerError, format!("{}\n", e).into_bytes()),
+        };
+        info!("{} {} {}", req.method, req.uri, status);
+        *res.status_mut() = status;
+        if let Err(e) = res.send(&body) {
+            error!("error sending response: {}", e)
+        }
+    }
+}
+
+pub fn run(args: &ArgMatches) -> Result<(), Error> {
+    let opts = BasicOptions::from_args(args)?;
+    let mut tokens = Vec::new();
+    if let Some(token_file) = args.value_of("token-file") {
+        let mut s = String::new();
+        File::open(token_file)?.read_to_string(&mut s)?;
+        tokens.extend(s.lines().map(|l| l.trim()).filter(|l| !l.is_empty()).map(|l| l.to_string()))
+    }
+    let address = args.value_of("address").unwrap_or("127.0.0.1:8080");
+    let max_body_size = match args.value_of("max-body-size") {
+        Some(size) => size.parse().unwrap(),
+        None => DEFAULT_MAX_BODY_SIZE,
+    };
+    let server = Server::http(address)?;
+    println!("Serving {} on http://{}", opts.repo_root.display(), address);
+    server.handle(Serve {
+            repo_root: opts.repo_root.clone(),
+            tokens: tokens,
+            max_body_size: max_body_size,
+            apply_lock: Mutex::new(()),
+        })?;
+    Ok(())
+}
+
+pub fn explain(res: Result<(), Error>) {
+    default_explain(res)
+}
@@ -0,0 +1,171 @@
+use clap::{SubCommand, ArgMatches, Arg};
+use chrono;
+use commands::{BasicOptions, StaticSubcommand, default_explain};
+use libpijul::{Hash, Patch};
+use libpijul::fs_representation::{stash_dir, patch_file_name};
+use libpijul::patch::Record;
+use rand;
+use rustc_serialize::base64::{ToBase64, URL_SAFE};
+use std::fs::{File, create_dir_all, read_dir, remove_dir_all, remove_file, symlink_metadata};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use error::Error;
+use meta::Meta;
+
+pub fn invocation() -> StaticSubcommand {
+    return SubCommand::with_name("stash")
+        .about("Put unrecorded changes aside, and restore them later")
+        .arg(Arg::with_name("repository")
+             .long("repository")
+             .help("Path to a pijul repository. Defaults to the repository containing the \
+                    current directory.")
+             .takes_value(true))
+        .arg(Arg::with_name("branch")
+             .long("branch")
+             .help("The branch of the working copy. Defaults to the current branch.")
+             .takes_value(true))
+        .subcommand(SubCommand::with_name("save")
+                    .about("Save the unrecorded changes in a new stash, and revert them")
+                    .arg(Arg::with_name("message")
+                         .short("m")
+                         .long("name")
+                         .help("A name for this stash.")
+                         .takes_value(true)))
+        .subcommand(SubCommand::with_name("list")
+                    .about("List the stashes, most recent first"))
+        .subcommand(SubCommand::with_name("apply")
+                    .about("Apply a stash to the working copy, without recording it")
+                    .arg(Arg::with_name("stash")
+                         .help("The number of the stash in `pijul stash list`, or its hash. \
+                                Defaults to the most recent stash.")))
+        .subcommand(SubCommand::with_name("drop")
+                    .about("Delete a stash")
+                    .arg(Arg::with_name("stash")
+                         .help("The number of the stash in `pijul stash list`, or its hash. \
+                                Defaults to the most recent stash.")))
+}
+
+/// The stashes of a repository, most recent first.
+fn list_stashes(repo_root: &Path) -> Result<Vec<(Hash, Patch)>, Error> {
+    let mut stashes = Vec::new();
+    if let Ok(entries) = read_dir(stash_dir(repo_root)) {
+        for entry in entries {
+            let f = File::open(entry?.path())?;
+            let (hash, _, patch) = Patch::from_reader_compressed(&mut BufReader::new(f))?;
+            stashes.push((hash, patch))
+        }
+    }
+    stashes.sort_by(|a, b| b.1.header.timestamp.cmp(&a.1.header.timestamp));
+    Ok(stashes)
+}
+
+/// Finds a stash given either by its number, or by its hash.
+fn find_stash(repo_root: &Path, stash: Option<&str>) -> Result<(Hash, Patch), Error> {
+    let stash = stash.unwrap_or("0");
+    let mut stashes = list_stashes(repo_root)?;
+    let position = if let Ok(n) = stash.parse::<usize>() {
+        if n < stashes.len() { Some(n) } else { None }
+    } else {
+        stashes.iter().position(|&(ref h, _)| h.to_base64(URL_SAFE) == stash)
+    };
+    match position {
+        Some(n) => Ok(stashes.swap_remove(n)),
+        None => Err(Error::NoSuchStash(stash.to_string())),
+    }
+}
+
+fn save(opts: &BasicOptions, branch_name: &str, name: Option<&str>) -> Result<(), Error> {
+    let repo = opts.open_and_grow_repo(409600)?;
+    let mut txn = repo.mut_txn_begin(rand::thread_rng())?;
+    let defaults = Meta::load(&opts.repo_root).file_attributes();
+    let (changes, _) = txn.record(branch_name, &opts.repo_root, &[], false, &defaults)?;
+    if changes.is_empty() {
+        println!("No changes to stash");
+        return Ok(())
+    }
+    let added: Vec<PathBuf> = changes.iter()
+        .filter_map(|c| match *c {
+            Record::FileAdd { ref name, .. } => Some(PathBuf::from(name)),
+            _ => None,
+        })
+        .collect();
+    let patch = {
+        let branch = txn.get_branch(branch_name).unwrap();
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => format!("WIP on {}", branch_name),
+        };
+        let changes = changes.into_iter().flat_map(|x| x.into_iter()).collect();
+        txn.new_patch(&branch, Vec::new(), name, None, chrono::UTC::now(), changes)
+    };
+    let dir = stash_dir(&opts.repo_root);
+    create_dir_all(&dir)?;
+    let hash = patch.save(&dir)?;
+
+    // Revert the working copy, and forget the files added since the
+    // last record, which the stash adds back.
+    txn.output_repository(branch_name, &opts.repo_root, &Patch::empty(), &[], &defaults)?;
+    for path in added.iter().rev() {
+        if let Ok(relative) = path.strip_prefix(&opts.repo_root) {
+            txn.remove_file(relative)?
+        }
+        match symlink_metadata(path) {
+            Ok(ref meta) if meta.is_dir() => remove_dir_all(path)?,
+            Ok(_) => remove_file(path)?,
+            Err(_) => {}
+        }
+    }
+    txn.commit()?;
+    println!("Saved stash {}", hash.to_base64(URL_SAFE));
+    Ok(())
+}
+
+fn apply(opts: &BasicOptions, branch_name: &str, hash: &Hash, stash: &Patch) -> Result<(), Error> {
+    let defaults = Meta::load(&opts.repo_root).file_attributes();
+    let mut increase = 409600 + stash.size_upper_bound() as u64;
+    loop {
+        let result = {
+            let repo = opts.open_and_grow_repo(increase)?;
+            let mut txn = repo.mut_txn_begin(rand::thread_rng())?;
+            txn.apply_stash(branch_name, &opts.repo_root, hash, stash, &defaults)
+                .and_then(|_| txn.commit())
+        };
+        match result {
+            Err(ref e) if e.lacks_space() => increase *= 2,
+            Err(e) => return Err(Error::Repository(e)),
+            Ok(()) => return Ok(()),
+        }
+    }
+}
+
+pub fn run(args: &ArgMatches) -> Result<(), Error> {
+    let opts = BasicOptions::from_args(args)?;
+    let branch_name = opts.branch();
+    match args.subcommand() {
+        ("save", Some(args)) => save(&opts, &branch_name, args.value_of("message"))?,
+        ("apply", Some(args)) => {
+            let (hash, stash) = find_stash(&opts.repo_root, args.value_of("stash"))?;
+            apply(&opts, &branch_name, &hash, &stash)?;
+            println!("Applied stash {}", hash.to_base64(URL_SAFE));
+        }
+        ("drop", Some(args)) => {
+            let (hash, _) = find_stash(&opts.repo_root, args.value_of("stash"))?;
+            remove_file(stash_dir(&opts.repo_root).join(patch_file_name(hash.as_ref())))?;
+            println!("Dropped stash {}", hash.to_base64(URL_SAFE));
+        }
+        _ => {
+            for (i, &(ref hash, ref stash)) in list_stashes(&opts.repo_root)?.iter().enumerate() {
+                println!("{}: {} {} {}",
+                         i,
+                         hash.to_base64(URL_SAFE),
+                         stash.header.timestamp.format("%F %T"),
+                         stash.header.name)
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn explain(res: Result<(), Error>) {
+    default_explain(res)
+}
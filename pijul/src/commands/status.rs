@@ -0,0 +1,125 @@
+use clap::{SubCommand, ArgMatches, Arg};
+use commands::{BasicOptions, StaticSubcommand, default_explain};
+use libpijul::{PathStatus, StatusKind};
+use rustc_serialize::json::{Json, ToJson};
+use std::collections::BTreeMap;
+use error::Error;
+use meta::Meta;
+
+pub fn invocation() -> StaticSubcommand {
+    return SubCommand::with_name("status")
+        .about("Show the files changed since the last record, and the untracked files")
+        .arg(Arg::with_name("repository")
+             .long("repository")
+             .help("Path to a pijul repository. Defaults to the repository containing the \
+                    current directory.")
+             .takes_value(true))
+        .arg(Arg::with_name("branch")
+             .long("branch")
+             .help("The branch to compare the working copy with. Defaults to the current branch.")
+             .takes_value(true))
+        .arg(Arg::with_name("short")
+             .short("s")
+             .long("short")
+             .help("Show one file per line, prefixed by a one-letter code: A (added), \
+                    D (deleted), ! (missing), R (moved), M (modified) or ? (untracked)."))
+        .arg(Arg::with_name("full-check")
+             .long("full-check")
+             .help("Read all files, including those that look unchanged since the last check."))
+        .arg(Arg::with_name("json")
+             .long("json")
+             .help("Output the status in JSON.")
+             .conflicts_with("short"))
+}
+
+fn short_code(kind: &StatusKind) -> char {
+    match *kind {
+        StatusKind::Added => 'A',
+        StatusKind::Deleted => 'D',
+        StatusKind::Missing => '!',
+        StatusKind::Moved { .. } => 'R',
+        StatusKind::Modified => 'M',
+        StatusKind::Untracked => '?',
+    }
+}
+
+fn kind_name(kind: &StatusKind) -> &'static str {
+    match *kind {
+        StatusKind::Added => "added",
+        StatusKind::Deleted => "deleted",
+        StatusKind::Missing => "missing",
+        StatusKind::Moved { .. } => "moved",
+        StatusKind::Modified => "modified",
+        StatusKind::Untracked => "untracked",
+    }
+}
+
+fn print_file(file: &PathStatus) {
+    match file.kind {
+        StatusKind::Moved { ref from } => {
+            println!("    {:10}{} -> {}", "moved:", from.display(), file.path.display())
+        }
+        ref kind => println!("    {:10}{}", format!("{}:", kind_name(kind)), file.path.display()),
+    }
+}
+
+fn file_to_json(file: &PathStatus) -> Json {
+    let mut f = BTreeMap::new();
+    f.insert("path".to_string(), file.path.to_string_lossy().to_json());
+    f.insert("status".to_string(), kind_name(&file.kind).to_json());
+    if let StatusKind::Moved { ref from } = file.kind {
+        f.insert("from".to_string(), from.to_string_lossy().to_json());
+    }
+    Json::Object(f)
+}
+
+pub fn run(args: &ArgMatches) -> Result<(), Error> {
+    let opts = BasicOptions::from_args(args)?;
+    let branch_name = opts.branch();
+    let repo = opts.open_repo()?;
+    let txn = repo.txn_begin()?;
+    let defaults = Meta::load(&opts.repo_root).file_attributes();
+    let files = match txn.get_branch(&branch_name) {
+        Some(branch) => {
+            txn.status(&branch, &opts.repo_root, args.is_present("full-check"), &defaults)?
+        }
+        None => return Err(Error::NoSuchBranch),
+    };
+    if args.is_present("json") {
+        let files: Vec<Json> = files.iter().map(file_to_json).collect();
+        println!("{}", Json::Array(files).pretty());
+    } else if args.is_present("short") {
+        for file in files.iter() {
+            match file.kind {
+                StatusKind::Moved { ref from } => {
+                    println!("R {} -> {}", from.display(), file.path.display())
+                }
+                ref kind => println!("{} {}", short_code(kind), file.path.display()),
+            }
+        }
+    } else {
+        println!("On branch {}", branch_name);
+        let (untracked, changed): (Vec<_>, Vec<_>) =
+            files.iter().partition(|f| f.kind == StatusKind::Untracked);
+        if !changed.is_empty() {
+            println!("Changes not yet recorded:");
+            for file in changed {
+                print_file(file)
+            }
+        }
+        if !untracked.is_empty() {
+            println!("Untracked files:");
+            for file in untracked {
+                println!("    {}", file.path.display())
+            }
+        }
+        if files.is_empty() {
+            println!("Nothing to record, working copy clean");
+        }
+    }
+    Ok(())
+}
+
+pub fn explain(res: Result<(), Error>) {
+    default_explain(res)
+}
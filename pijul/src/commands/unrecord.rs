@@ -117,9 +117,9 @@ pub fn run(args: &ArgMatches) -> Result<(), Error> {
     }
     drop(repo);
 
-    let repo_dir = opts.repo_dir();
+    let pristine_dir = opts.pristine_dir();
     loop {
-        match unrecord_no_resize(&repo_dir, &opts.repo_root, &branch_name, &mut selected, increase) {
+        match unrecord_no_resize(&pristine_dir, &opts.repo_root, &branch_name, &mut selected, increase) {
             Err(ref e) if e.lacks_space() => { increase *= 2 },
             e => return e.map_err(Error::Repository)
         }
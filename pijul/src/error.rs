@@ -3,7 +3,8 @@ use std::error;
 use std::fmt;
 use std::string;
 use std::path;
-use {app_dirs, thrussh, libpijul, rustc_serialize, hyper, rustyline, term, toml};
+use {app_dirs, thrussh, thrussh_keys, libpijul, regex, rustc_serialize, hyper, rustyline, term, toml, user};
+use rustc_serialize::base64::{ToBase64, URL_SAFE};
 
 #[derive(Debug)]
 pub enum Error {
@@ -28,6 +29,28 @@ pub enum Error {
     NoSuchBranch,
     IsDirectory,
     AppDirsError(app_dirs::AppDirsError),
+    NoSigningKey,
+    InvalidPublicKey(String),
+    UnsignedPatch(libpijul::Hash),
+    UnknownSigningKey(libpijul::Hash),
+    InvalidSignature(libpijul::Hash),
+    ModifiedConflictedFile(path::PathBuf),
+    Regex(regex::Error),
+    InvalidDate(String),
+    InvalidLimit(String),
+    PendingChanges,
+    CannotCarryChanges(String),
+    NoSuchStash(String),
+    InvalidLineRange(String),
+    HttpStatus(u16, String),
+    SshKey(thrussh_keys::Error),
+    SshHostNotFound(String),
+    SshAuthenticationFailed(String),
+    UserName(user::Error),
+    NoSuchRemote(String),
+    RemoteExists(String),
+    CannotInitRemote(String),
+    UnsupportedSshKey(path::PathBuf),
 }
 
 impl Error {
@@ -65,6 +88,39 @@ impl fmt::Display for Error {
             Error::NoSuchBranch => write!(f, "No such branch"),
             Error::IsDirectory => write!(f, "Is a directory"),
             Error::AppDirsError(ref err) => write!(f, "Error finding configuration path: {}", err),
+            Error::NoSigningKey => write!(f, "No signing key, generate one with `pijul key generate`"),
+            Error::InvalidPublicKey(ref k) => write!(f, "Invalid public key {}", k),
+            Error::UnsignedPatch(ref h) => write!(f, "Patch {} is not signed", h.to_base64(URL_SAFE)),
+            Error::UnknownSigningKey(ref h) => {
+                write!(f, "Patch {} is signed by an unknown key", h.to_base64(URL_SAFE))
+            }
+            Error::InvalidSignature(ref h) => {
+                write!(f, "Patch {} has an invalid signature", h.to_base64(URL_SAFE))
+            }
+            Error::ModifiedConflictedFile(ref p) => {
+                write!(f, "File {} has unrecorded changes, record or revert them first", p.display())
+            }
+            Error::Regex(ref err) => write!(f, "Regex: {}", err),
+            Error::InvalidDate(ref d) => write!(f, "Invalid date {}", d),
+            Error::InvalidLimit(ref n) => write!(f, "Invalid limit {}", n),
+            Error::PendingChanges => {
+                write!(f, "The working copy has unrecorded changes; record them, or use --carry \
+                           to carry them over to the new branch, or --force to discard them")
+            }
+            Error::CannotCarryChanges(ref b) => {
+                write!(f, "Unrecorded changes depend on patches not applied on branch {}", b)
+            }
+            Error::NoSuchStash(ref s) => write!(f, "No such stash {}", s),
+            Error::InvalidLineRange(ref r) => write!(f, "Invalid line range {}", r),
+            Error::HttpStatus(code, ref msg) => write!(f, "HTTP error {}: {}", code, msg),
+            Error::SshKey(ref err) => write!(f, "SSH key: {}", err),
+            Error::SshHostNotFound(ref host) => write!(f, "Could not resolve SSH host {}", host),
+            Error::SshAuthenticationFailed(ref host) => write!(f, "Could not authenticate to {}: no key was accepted", host),
+            Error::UserName(ref err) => write!(f, "Could not get the user name: {}", err),
+            Error::NoSuchRemote(ref r) => write!(f, "No such remote {}", r),
+            Error::RemoteExists(ref r) => write!(f, "Remote {} already exists", r),
+            Error::CannotInitRemote(ref r) => write!(f, "Cannot create a repository at {}: only local and SSH remotes can be initialized", r),
+            Error::UnsupportedSshKey(ref p) => write!(f, "Unsupported SSH key {}: only Ed25519 and RSA keys are supported", p.display()),
             // Error::TomlDe(ref e) => write!(f, "Toml de err: {}", e),
             // Error::TomlSer(ref e) => write!(f, "Toml ser err: {}", e),
         }
@@ -95,6 +151,28 @@ impl error::Error for Error {
             Error::NoSuchBranch => "No such branch",
             Error::IsDirectory => "Is a directory",
             Error::AppDirsError(ref err) => err.description(),
+            Error::NoSigningKey => "No signing key",
+            Error::InvalidPublicKey(_) => "Invalid public key",
+            Error::UnsignedPatch(_) => "Unsigned patch",
+            Error::UnknownSigningKey(_) => "Patch signed by an unknown key",
+            Error::InvalidSignature(_) => "Invalid patch signature",
+            Error::ModifiedConflictedFile(_) => "Conflicted file has unrecorded changes",
+            Error::Regex(ref err) => err.description(),
+            Error::InvalidDate(_) => "Invalid date",
+            Error::InvalidLimit(_) => "Invalid limit",
+            Error::PendingChanges => "Unrecorded changes in the working copy",
+            Error::CannotCarryChanges(_) => "Unrecorded changes cannot be carried over",
+            Error::NoSuchStash(_) => "No such stash",
+            Error::InvalidLineRange(_) => "Invalid line range",
+            Error::HttpStatus(_, _) => "HTTP error",
+            Error::SshKey(ref err) => err.description(),
+            Error::SshHostNotFound(_) => "Could not resolve SSH host",
+            Error::SshAuthenticationFailed(_) => "SSH authentication failed",
+            Error::UserName(ref err) => err.description(),
+            Error::NoSuchRemote(_) => "No such remote",
+            Error::RemoteExists(_) => "Remote already exists",
+            Error::CannotInitRemote(_) => "Cannot create a remote repository",
+            Error::UnsupportedSshKey(_) => "Unsupported SSH key",
             // Error::TomlDe(ref e) => e.description(),
             // Error::TomlSer(ref e) => e.description(),
         }
@@ -123,6 +201,28 @@ impl error::Error for Error {
             Error::NoSuchBranch => None,
             Error::IsDirectory => None,
             Error::AppDirsError(ref err) => Some(err),
+            Error::NoSigningKey => None,
+            Error::InvalidPublicKey(_) => None,
+            Error::UnsignedPatch(_) => None,
+            Error::UnknownSigningKey(_) => None,
+            Error::InvalidSignature(_) => None,
+            Error::ModifiedConflictedFile(_) => None,
+            Error::Regex(ref err) => Some(err),
+            Error::InvalidDate(_) => None,
+            Error::InvalidLimit(_) => None,
+            Error::PendingChanges => None,
+            Error::CannotCarryChanges(_) => None,
+            Error::NoSuchStash(_) => None,
+            Error::InvalidLineRange(_) => None,
+            Error::HttpStatus(_, _) => None,
+            Error::SshKey(ref err) => Some(err),
+            Error::SshHostNotFound(_) => None,
+            Error::SshAuthenticationFailed(_) => None,
+            Error::UserName(ref err) => Some(err),
+            Error::NoSuchRemote(_) => None,
+            Error::RemoteExists(_) => None,
+            Error::CannotInitRemote(_) => None,
+            Error::UnsupportedSshKey(_) => None,
             // Error::TomlDe(ref err) => Some(err),
             // Error::TomlSer(ref err) => Some(err),
         }
@@ -135,6 +235,12 @@ impl From<io::Error> for Error {
     }
 }
 
+impl From<regex::Error> for Error {
+    fn from(err: regex::Error) -> Error {
+        Error::Regex(err)
+    }
+}
+
 impl From<thrussh::Error> for Error {
     fn from(err: thrussh::Error) -> Error {
         Error::SSH(err)
@@ -150,6 +256,18 @@ impl From<thrussh::HandlerError<Error>> for Error {
     }
 }
 
+impl From<thrussh_keys::Error> for Error {
+    fn from(err: thrussh_keys::Error) -> Error {
+        Error::SshKey(err)
+    }
+}
+
+impl From<user::Error> for Error {
+    fn from(err: user::Error) -> Error {
+        Error::UserName(err)
+    }
+}
+
 impl From<libpijul::error::Error> for Error {
     fn from(err: libpijul::error::Error) -> Error {
         Error::Repository(err)
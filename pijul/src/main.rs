@@ -13,6 +13,7 @@ extern crate libpijul;
 extern crate log;
 extern crate rand;
 extern crate regex;
+extern crate rpassword;
 extern crate rustc_serialize;
 extern crate rustyline;
 extern crate serde;
@@ -22,7 +23,10 @@ extern crate shell_escape;
 extern crate tar;
 extern crate term;
 extern crate thrussh;
+extern crate thrussh_keys;
 extern crate tokio_core;
+#[cfg(unix)]
+extern crate tokio_uds;
 extern crate toml;
 extern crate user;
 
@@ -79,7 +83,15 @@ fn main() {
                                "checkout" => checkout,
                                "diff" => diff,
                                "blame" => blame,
-                               "dist" => dist
+                               "dist" => dist,
+                               "key" => key,
+                               "conflicts" => conflicts,
+                               "resolve" => resolve,
+                               "status" => status,
+                               "stash" => stash,
+                               "log" => log,
+                               "serve" => serve,
+                               "remote" => remotes
                                );
     let time1 = chrono::Local::now();
     info!("The command took: {:?}", time1.signed_duration_since(time0));
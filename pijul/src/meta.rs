@@ -1,8 +1,12 @@
 use app_dirs::{app_root, get_app_root, AppDataType, AppInfo};
-use libpijul::fs_representation;
+use libpijul::{fs_representation, DiffAlgorithm, Hash};
+use libpijul::attributes::{EolPolicy, FileAttributes};
+use libpijul::signature::{SigningKey, PublicKey, read_signature};
+use rustc_serialize::base64::{FromBase64, ToBase64, URL_SAFE};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{Read, Write, stderr};
 use toml;
 
 use error::Error;
@@ -16,6 +20,7 @@ const APP_INFO: AppInfo = AppInfo {
 
 const META_FILE_NAME: &'static str = "meta.toml";
 const GLOBAL_META_FILE_NAME: &'static str = "global.toml";
+const KEYS_FILE_NAME: &'static str = "keys.toml";
 
 #[derive(Debug, Deserialize, Serialize)]
 pub enum Repository {
@@ -23,21 +28,89 @@ pub enum Repository {
     SSH { address: String, port: u16 },
 }
 
+/// What to do with unsigned patches, or patches signed by keys that
+/// are not trusted, when applying or pulling patches. Patches with
+/// an invalid signature are always rejected, and if no policy is
+/// configured, nothing else is checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SignaturePolicy {
+    #[serde(rename = "warn")]
+    Warn,
+    #[serde(rename = "reject-unsigned")]
+    RejectUnsigned,
+    #[serde(rename = "reject-unknown")]
+    RejectUnknown,
+}
+
+/// Settings for connecting to an SSH host, in the `[ssh.<host>]`
+/// sections of the configuration files.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SshHost {
+    /// User name on the host, if not given in the remote address.
+    pub user: Option<String>,
+    /// Port of the host, if not given with `--port`.
+    pub port: Option<u16>,
+    /// Private key to authenticate with, instead of the default keys
+    /// in `~/.ssh`.
+    pub identity_file: Option<String>,
+}
+
+/// A named remote repository, in the `[remotes.<name>]` sections of
+/// the configuration files.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NamedRemote {
+    /// Address of the repository, in any of the forms accepted by
+    /// `pijul pull`.
+    pub address: String,
+    /// Port of the ssh server, if not given with `--port`.
+    pub port: Option<u16>,
+    /// Branch of the remote to pull from by default.
+    pub pull_branch: Option<String>,
+    /// Branch of the remote to push to by default.
+    pub push_branch: Option<String>,
+    /// Private key to authenticate to the ssh server with, if not
+    /// given with `--identity`.
+    pub identity_file: Option<String>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Meta {
     pub default_authors: Vec<String>,
+    pub signature_policy: Option<SignaturePolicy>,
+    /// How the lines of modified files are aligned when recording,
+    /// `"myers"` (the default) or `"patience"`.
+    pub diff_algorithm: Option<DiffAlgorithm>,
+    /// End-of-line policy of the files not given one in the
+    /// attributes file.
+    pub eol: Option<EolPolicy>,
     pub pull: Option<Repository>,
     pub push: Option<Repository>,
+    #[serde(default)]
+    pub ssh: BTreeMap<String, SshHost>,
+    #[serde(default)]
+    pub remotes: BTreeMap<String, NamedRemote>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct GlobalMeta {
     pub default_authors: Vec<String>,
+    pub signature_policy: Option<SignaturePolicy>,
+    pub eol: Option<EolPolicy>,
+    #[serde(default)]
+    pub ssh: BTreeMap<String, SshHost>,
+    #[serde(default)]
+    pub remotes: BTreeMap<String, NamedRemote>,
 }
 
 impl GlobalMeta {
     fn new() -> GlobalMeta {
-        GlobalMeta { default_authors: Vec::new() }
+        GlobalMeta {
+            default_authors: Vec::new(),
+            signature_policy: None,
+            eol: None,
+            ssh: BTreeMap::new(),
+            remotes: BTreeMap::new(),
+        }
     }
 
     fn load() -> Result<GlobalMeta, Error> {
@@ -68,14 +141,31 @@ impl GlobalMeta {
         meta.default_authors = authors.clone();
         meta.save()
     }
+
+    /// The named remotes of the global configuration file.
+    pub fn load_remotes() -> Result<BTreeMap<String, NamedRemote>, Error> {
+        Ok(GlobalMeta::load()?.remotes)
+    }
+
+    /// Modifies the global configuration file with new named remotes.
+    pub fn save_remotes(remotes: BTreeMap<String, NamedRemote>) -> Result<(), Error> {
+        let mut meta = GlobalMeta::load()?;
+        meta.remotes = remotes;
+        meta.save()
+    }
 }
 
 impl Meta {
     fn new() -> Meta {
         Meta {
             default_authors: Vec::new(),
+            signature_policy: None,
+            diff_algorithm: None,
+            eol: None,
             push: None,
             pull: None,
+            ssh: BTreeMap::new(),
+            remotes: BTreeMap::new(),
         }
     }
 
@@ -96,6 +186,24 @@ impl Meta {
         meta
     }
 
+    /// Loads the global configuration only, for commands run outside
+    /// of a repository.
+    pub fn load_global() -> Meta {
+        let mut meta = Meta::new();
+        meta.merge_global_meta();
+        meta
+    }
+
+    /// The attributes of the files of the repository, where its
+    /// attributes file does not set them.
+    pub fn file_attributes(&self) -> FileAttributes {
+        FileAttributes {
+            diff: self.diff_algorithm,
+            eol: self.eol,
+            ..FileAttributes::default()
+        }
+    }
+
     fn path(repo_root: &Path) -> PathBuf {
         let mut path = fs_representation::repo_dir(repo_root);
         path.push(META_FILE_NAME);
@@ -118,6 +226,24 @@ impl Meta {
         if self.default_authors.is_empty() {
             self.default_authors = global.default_authors
         }
+        if self.signature_policy.is_none() {
+            self.signature_policy = global.signature_policy
+        }
+        if self.eol.is_none() {
+            self.eol = global.eol
+        }
+        for (host, settings) in global.ssh {
+            self.ssh.entry(host).or_insert(settings);
+        }
+        for (name, remote) in global.remotes {
+            self.remotes.entry(name).or_insert(remote);
+        }
+    }
+
+    /// The settings for SSH host `host`, which are all unset if the
+    /// host is not configured.
+    pub fn ssh_host(&self, host: &str) -> SshHost {
+        self.ssh.get(host).cloned().unwrap_or_default()
     }
 
     fn save(&self, repo_root: &Path) -> Result<(), Error> {
@@ -140,6 +266,18 @@ impl Meta {
         meta.save(repo_root)
     }
 
+    /// The named remotes of the local configuration file only.
+    pub fn load_remotes(repo_root: &Path) -> BTreeMap<String, NamedRemote> {
+        Meta::load_local(repo_root).map(|meta| meta.remotes).unwrap_or(BTreeMap::new())
+    }
+
+    /// Modifies the local configuration file with new named remotes.
+    pub fn save_remotes(repo_root: &Path, remotes: BTreeMap<String, NamedRemote>) -> Result<(), Error> {
+        let mut meta = Meta::load_local(repo_root).unwrap_or(Meta::new());
+        meta.remotes = remotes;
+        meta.save(repo_root)
+    }
+
     /// Modifies the local configuration file with a new value for `pull`.
     pub fn save_pull(repo_root: &Path, pull: Repository) -> Result<(), Error> {
         let mut meta = Meta::load_local(repo_root).unwrap_or(Meta::new());
@@ -162,3 +300,156 @@ impl Meta {
         }
     }
 }
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TrustedKey {
+    pub name: String,
+    pub public_key: String,
+}
+
+/// The user's signing key, and the public keys they trust, stored in
+/// the same directory as the global configuration.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct KeyStore {
+    /// PKCS#8 encoding of the signing key, in base64.
+    pub secret_key: Option<String>,
+    #[serde(default)]
+    pub trusted: Vec<TrustedKey>,
+}
+
+impl KeyStore {
+    fn new() -> KeyStore {
+        KeyStore {
+            secret_key: None,
+            trusted: Vec::new(),
+        }
+    }
+
+    pub fn load() -> Result<KeyStore, Error> {
+        let dir = get_app_root(AppDataType::UserConfig, &APP_INFO);
+        if let Ok(mut path) = dir {
+            path.push(KEYS_FILE_NAME);
+            if path.exists() {
+                let mut s = String::new();
+                File::open(path)?.read_to_string(&mut s)?;
+                return Ok(toml::from_str(&s)?);
+            }
+        }
+        Ok(KeyStore::new())
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let mut path = app_root(AppDataType::UserConfig, &APP_INFO)?;
+        path.push(KEYS_FILE_NAME);
+        let mut f = File::create(&path)?;
+        set_private_permissions(&f)?;
+        f.write_all(toml::to_string(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn path() -> Result<PathBuf, Error> {
+        let mut path = app_root(AppDataType::UserConfig, &APP_INFO)?;
+        path.push(KEYS_FILE_NAME);
+        Ok(path)
+    }
+
+    pub fn signing_key(&self) -> Result<SigningKey, Error> {
+        match self.secret_key {
+            Some(ref k) => {
+                let pkcs8 = k.from_base64().map_err(|_| Error::NoSigningKey)?;
+                Ok(SigningKey::from_pkcs8(&pkcs8)?)
+            }
+            None => Err(Error::NoSigningKey),
+        }
+    }
+
+    /// Generates a new signing key, replacing the current one.
+    pub fn generate(&mut self) -> Result<PublicKey, Error> {
+        let (key, pkcs8) = SigningKey::generate()?;
+        self.secret_key = Some(pkcs8.to_base64(URL_SAFE));
+        Ok(key.public_key())
+    }
+
+    /// Trusts `public_key` under the name `name`, replacing any other
+    /// key with the same name.
+    pub fn trust(&mut self, name: &str, public_key: &PublicKey) {
+        self.trusted.retain(|k| k.name != name);
+        self.trusted.push(TrustedKey {
+            name: name.to_string(),
+            public_key: public_key.to_base64(),
+        })
+    }
+
+    /// The name under which `public_key` is trusted. The user's own
+    /// key is always trusted.
+    pub fn trusted_name(&self, public_key: &PublicKey) -> Option<&str> {
+        if let Ok(key) = self.signing_key() {
+            if &key.public_key() == public_key {
+                return Some("self")
+            }
+        }
+        self.trusted.iter()
+            .find(|k| PublicKey::from_base64(&k.public_key).as_ref() == Some(public_key))
+            .map(|k| k.name.as_str())
+    }
+
+    /// Checks the signatures of patches `hashes` of repository
+    /// `repo_root` against `policy`, printing a warning for each
+    /// patch accepted despite not being signed by a trusted key.
+    pub fn check_signatures<'a, I: Iterator<Item = &'a Hash>>(&self,
+                                                              repo_root: &Path,
+                                                              hashes: I,
+                                                              policy: Option<SignaturePolicy>)
+                                                              -> Result<(), Error> {
+        for hash in hashes {
+            match read_signature(repo_root, hash.as_ref())? {
+                Some(ref signature) if !signature.verify(hash.as_ref()) => {
+                    return Err(Error::InvalidSignature(hash.clone()))
+                }
+                Some(ref signature) => {
+                    if let Some(name) = self.trusted_name(&signature.public_key) {
+                        debug!("patch {:?} signed by {:?}", hash, name);
+                        continue
+                    }
+                    match policy {
+                        Some(SignaturePolicy::RejectUnknown) => {
+                            return Err(Error::UnknownSigningKey(hash.clone()))
+                        }
+                        Some(_) => {
+                            writeln!(stderr(),
+                                     "Warning: patch {} is signed by unknown key {}",
+                                     hash.to_base64(URL_SAFE),
+                                     signature.public_key.to_base64())?;
+                        }
+                        None => {}
+                    }
+                }
+                None => {
+                    match policy {
+                        Some(SignaturePolicy::Warn) => {
+                            writeln!(stderr(),
+                                     "Warning: patch {} is not signed",
+                                     hash.to_base64(URL_SAFE))?;
+                        }
+                        Some(_) => return Err(Error::UnsignedPatch(hash.clone())),
+                        None => {}
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(windows))]
+fn set_private_permissions(f: &File) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut perms = f.metadata()?.permissions();
+    perms.set_mode(0o600);
+    f.set_permissions(perms)?;
+    Ok(())
+}
+#[cfg(windows)]
+fn set_private_permissions(_: &File) -> Result<(), Error> {
+    Ok(())
+}
@@ -0,0 +1,87 @@
+#!/usr/bin/env bats
+
+load test_helper
+
+make_history() {
+    pijul init
+    echo a > a.txt
+    echo b > b.txt
+    pijul add a.txt b.txt
+    pijul record -a -m "first patch" -A alice
+    echo aa >> a.txt
+    pijul record -a -m "second patch" -A bob
+    echo bb >> b.txt
+    pijul record -a -m "fix b" -A alice
+}
+
+@test "changes filtered by author and name" {
+    make_history
+    run pijul changes --author alice --format one-line
+    assert_success 'fix b'
+    [[ ${#lines[@]} -eq 2 ]]
+
+    run pijul changes --name '^second'
+    assert_success 'second patch'
+    [[ ! "$output" =~ "fix b" ]]
+}
+
+@test "changes filtered by path" {
+    make_history
+    run pijul changes --path a.txt --format one-line
+    assert_success 'second patch'
+    [[ ${#lines[@]} -eq 2 ]]
+    [[ ! "$output" =~ "fix b" ]]
+}
+
+@test "changes filtered by date" {
+    make_history
+    run pijul changes --until 2000-01-01
+    assert_success '^$'
+    run pijul changes --since 2000-01-01 --format one-line
+    [[ ${#lines[@]} -eq 3 ]]
+    run pijul changes --since yesterday
+    assert_failure
+}
+
+@test "changes limit, template and json" {
+    make_history
+    run pijul changes -n 1 --template '{name} by {authors}'
+    assert_success '^fix b by alice$'
+
+    run pijul changes --format json
+    assert_success '"name": "first patch"'
+}
+
+@test "template placeholders in patch names are not expanded" {
+    make_history
+    echo c > c.txt
+    pijul add c.txt
+    pijul record -a -m 'about {authors}' -A carol
+    run pijul changes -n 1 --template '{name} by {authors}'
+    assert_success '^about \{authors\} by carol$'
+}
+
+@test "log of a moved file" {
+    make_history
+    pijul mv b.txt c.txt
+    pijul record -a -m "rename b" -A carol
+    echo cc >> c.txt
+    pijul record -a -m "edit c" -A carol
+
+    run pijul log c.txt --format one-line
+    assert_success 'edit c'
+    assert_success 'rename b'
+    assert_success 'fix b'
+    assert_success 'first patch'
+    [[ ${#lines[@]} -eq 4 ]]
+
+    run pijul log a.txt --format one-line -n 1
+    assert_success '^[^ ]+ [0-9-]+ second patch$'
+
+    run pijul log c.txt --format json
+    assert_success '"name": "rename b"'
+
+    run pijul log c.txt -n 2 --template '{name} by {authors}'
+    assert_success '^edit c by carol
+rename b by carol$'
+}
@@ -0,0 +1,103 @@
+#!/usr/bin/env bats
+
+load test_helper
+
+@test "add skips ignored files" {
+    pijul init
+    echo "*.o" > .pijulignore
+    touch main.c main.o
+    run pijul add main.c main.o
+    assert_success "Not adding ignored file main.o"
+    run pijul ls
+    assert_success main.c
+    [[ ! "$output" =~ main.o ]]
+}
+
+@test "ignored directories and negation" {
+    pijul init
+    printf "target/\n*.log\n!keep.log\n" > .pijulignore
+    mkdir -p target/debug src
+    touch target/debug/out src/main.rs src/build.log src/keep.log
+    run pijul diff --untracked
+    assert_success
+    [[ "${lines[0]}" == ".pijulignore" ]]
+    [[ "${lines[1]}" == "src/keep.log" ]]
+    [[ "${lines[2]}" == "src/main.rs" ]]
+    [[ "${#lines[@]}" -eq 3 ]]
+}
+
+@test "nested ignore files" {
+    pijul init
+    echo "*.tmp" > .pijulignore
+    mkdir sub
+    printf "!*.tmp\nlocal\n" > sub/.pijulignore
+    touch a.tmp local sub/b.tmp sub/local
+    pijul add .pijulignore sub/.pijulignore
+    run pijul diff --untracked
+    assert_success
+    [[ "${lines[0]}" == "local" ]]
+    [[ "${lines[1]}" == "sub/b.tmp" ]]
+    [[ "${#lines[@]}" -eq 2 ]]
+}
+
+@test "double star patterns" {
+    mkdir a
+    cd a
+    pijul init
+    printf "docs/**/*.tmp\n**/cache\n" > .pijulignore
+    mkdir -p docs/x/y src/cache lib/deep
+    touch top.tmp docs/a.tmp docs/x/y/b.tmp docs/keep.txt src/cache/c lib/deep/cache
+    run pijul diff --untracked
+    assert_success
+    [[ "${lines[0]}" == ".pijulignore" ]]
+    [[ "${lines[1]}" == "docs/keep.txt" ]]
+    [[ "${lines[2]}" == "top.tmp" ]]
+    [[ "${#lines[@]}" -eq 3 ]]
+}
+
+@test "character classes and escaped characters" {
+    mkdir a
+    cd a
+    pijul init
+    printf '%s\n' '[!a-c]*.o' 'log[0-9].txt' '\*.bak' 'file\?' > .pijulignore
+    touch a.o d.o log1.txt logx.txt '*.bak' x.bak 'file?' fileX
+    run pijul diff --untracked
+    assert_success
+    [[ "${lines[0]}" == ".pijulignore" ]]
+    [[ "${lines[1]}" == "a.o" ]]
+    [[ "${lines[2]}" == "fileX" ]]
+    [[ "${lines[3]}" == "logx.txt" ]]
+    [[ "${lines[4]}" == "x.bak" ]]
+    [[ "${#lines[@]}" -eq 5 ]]
+}
+
+@test "anchored and directory-only patterns" {
+    mkdir a
+    cd a
+    pijul init
+    printf "/build\nout/\nsub/gen\n" > .pijulignore
+    mkdir -p sub out other/sub
+    touch build sub/build out/x sub/out sub/gen other/sub/gen
+    run pijul diff --untracked
+    assert_success
+    [[ "${lines[0]}" == ".pijulignore" ]]
+    [[ "${lines[1]}" == "other/sub/gen" ]]
+    [[ "${lines[2]}" == "sub/build" ]]
+    [[ "${lines[3]}" == "sub/out" ]]
+    [[ "${#lines[@]}" -eq 4 ]]
+}
+
+@test "record keeps files added before being ignored" {
+    mkdir a
+    cd a
+    pijul init
+    mkdir target
+    touch file.txt target/out
+    pijul add file.txt target target/out
+    echo "/target" > .pijulignore
+    pijul record -a -m msg -A me
+    cd ..
+    pijul clone a b
+    [[ -f b/file.txt ]]
+    [[ -f b/target/out ]]
+}
@@ -305,3 +305,518 @@ load test_helper
     assert_files_equal a/toto b/toto
 }
 
+
+@test "symlinks are recorded as links" {
+    mkdir a
+    cd a
+    pijul init
+    mkdir dir
+    echo contents > dir/file.txt
+    ln -s dir/file.txt link
+    pijul add dir/file.txt link
+    pijul record -a -m msg -A me
+    cd ..
+    pijul clone a b
+    [[ -L b/link ]]
+    [[ "$(readlink b/link)" == "dir/file.txt" ]]
+    assert_files_equal a/dir/file.txt b/link
+}
+
+@test "changing a symlink target" {
+    mkdir a
+    cd a
+    pijul init
+    touch x y
+    ln -s x link
+    pijul add x y link
+    pijul record -a -m msg -A me
+    cd ..
+    pijul clone a b
+    cd a
+    ln -sfn y link
+    pijul record -a -m msg -A me
+    cd ..
+    pijul pull -a a b
+    [[ -L b/link ]]
+    [[ "$(readlink b/link)" == "y" ]]
+}
+
+@test "setgid directories are not links" {
+    mkdir a
+    cd a
+    pijul init
+    mkdir dir
+    chmod g+s dir
+    echo contents > dir/file.txt
+    pijul add dir dir/file.txt
+    pijul record -a -m msg -A me
+    run pijul status
+    assert_success "working copy clean"
+    cd ..
+    pijul clone a b
+    [[ -d b/dir && ! -L b/dir ]]
+    assert_files_equal a/dir/file.txt b/dir/file.txt
+}
+
+@test "list conflicts" {
+    make_single_file_repo a toto
+    pijul clone a b
+
+    run pijul conflicts --repository a
+    assert_success '^$'
+
+    append_random a/toto
+    append_random b/toto
+    pijul record --repository a -a -m msg -A me
+    pijul record --repository b -a -m msg -A me
+    pijul pull -a a b
+
+    run pijul conflicts --repository b
+    assert_success 'toto'
+    assert_success '2 sides'
+
+    run pijul conflicts --repository b --json
+    assert_success '"path": "toto"'
+}
+
+@test "resolve a conflict by taking a side" {
+    make_single_file_repo a toto
+    pijul clone a b
+
+    echo "from a" >> a/toto
+    echo "from b" >> b/toto
+    pijul record --repository a -a -m msg -A me
+    pijul record --repository b -a -m msg -A me
+    pijul pull -a a b
+    assert_file_contains b/toto '>>>>>'
+
+    run pijul resolve --repository b toto --take 1 -m resolve -A me
+    assert_success 'Recorded patch'
+    ! grep --quiet '>>>>>' b/toto
+    [[ $(grep -c '^from ' b/toto) -eq 1 ]]
+
+    run pijul conflicts --repository b
+    assert_success '^$'
+    run pijul record --repository b -a -m msg -A me
+    assert_success 'Nothing to record'
+
+    pijul pull -a b a
+    assert_files_equal a/toto b/toto
+}
+
+@test "patch as text" {
+    mkdir a
+    cd a
+    pijul init
+    printf "one\ntwo\nthree\n" > file.txt
+    pijul add file.txt
+    pijul record -a -m first -A me
+    printf "one\n2\nthree\n" > file.txt
+    pijul record -a -m second -A me
+    hash=$(pijul changes --template '{hash}' -n 1)
+
+    run pijul patch --text $hash
+    assert_success '--- file.txt'
+    assert_success '-two'
+    assert_success '\+2'
+    assert_success ' one'
+}
+
+@test "status" {
+    # Keep $HOME, where pijul saves the author, out of the working copy.
+    make_repo a
+    cd a
+    echo a > a.txt
+    echo b > b.txt
+    echo c > c.txt
+    echo d > d.txt
+    pijul add a.txt b.txt c.txt d.txt
+    pijul record -a -m "first" -A me
+    echo aa >> a.txt
+    pijul mv b.txt bb.txt
+    pijul remove c.txt
+    rm d.txt
+    echo e > e.txt
+    pijul add e.txt
+    echo f > f.txt
+
+    run pijul status --short
+    assert_success
+    assert_output "M a.txt
+R b.txt -> bb.txt
+D c.txt
+! d.txt
+A e.txt
+\? f.txt"
+
+    run pijul status --json
+    assert_success '"from": "b.txt"'
+
+    pijul record -a -m "second" -A me
+    rm c.txt f.txt
+    run pijul status
+    assert_success "Nothing to record"
+}
+
+@test "checkout with unrecorded changes" {
+    pijul init
+    echo a > file.txt
+    echo x > keep.txt
+    pijul add file.txt keep.txt
+    pijul record -a -m "first" -A me
+    pijul fork other
+    echo b >> file.txt
+    pijul record -a -m "second" -A me
+
+    echo c >> file.txt
+    run pijul checkout master
+    assert_failure "unrecorded changes"
+    assert_file_contains file.txt c
+    run pijul checkout master --carry
+    assert_failure "not applied on branch master"
+    pijul checkout master --force
+    run cat file.txt
+    assert_output "^a$"
+
+    echo y >> keep.txt
+    pijul checkout other --carry
+    assert_file_contains file.txt b
+    assert_file_contains keep.txt y
+    run pijul diff
+    assert_success "y"
+}
+
+@test "stash save and apply" {
+    make_repo a
+    cd a
+    echo a > file.txt
+    pijul add file.txt
+    pijul record -a -m "first" -A me
+
+    echo b >> file.txt
+    echo new > new.txt
+    pijul add new.txt
+    pijul stash save -m "wip"
+    run cat file.txt
+    assert_output "^a$"
+    [ ! -e new.txt ]
+    run pijul status
+    assert_success "Nothing to record"
+    run pijul stash list
+    assert_success "0: .* wip"
+
+    pijul stash apply
+    assert_file_contains file.txt b
+    assert_file_contains new.txt new
+    run pijul diff
+    assert_success "b"
+    run pijul status --short
+    assert_success "A new.txt"
+
+    pijul stash drop
+    run pijul stash list
+    assert_output "^$"
+    run pijul stash apply
+    assert_failure "No such stash"
+}
+
+@test "apply the same stash twice" {
+    pijul init
+    echo a > file.txt
+    pijul add file.txt
+    pijul record -a -m "first" -A me
+
+    echo b >> file.txt
+    pijul stash save -m "wip"
+    pijul stash apply
+    assert_file_contains file.txt b
+    pijul revert -a
+    run cat file.txt
+    assert_output "^a$"
+
+    pijul stash apply
+    assert_file_contains file.txt b
+    run pijul stash list
+    assert_success "0: .* wip"
+}
+
+@test "apply a conflicting stash twice" {
+    make_repo a
+    cd a
+    echo a > file.txt
+    pijul add file.txt
+    pijul record -a -m "first" -A me
+
+    echo stashed >> file.txt
+    pijul stash save -m "wip"
+    echo recorded >> file.txt
+    pijul record -a -m "second" -A me
+
+    pijul stash apply
+    assert_file_contains file.txt '>>>>>'
+    assert_file_contains file.txt stashed
+    assert_file_contains file.txt recorded
+    run pijul changes
+    assert_success second
+    [[ ! "$output" =~ wip ]]
+
+    pijul revert -a
+    run cat file.txt
+    assert_output "^a
+recorded$"
+
+    pijul stash apply
+    assert_file_contains file.txt '>>>>>'
+    assert_file_contains file.txt stashed
+    assert_file_contains file.txt recorded
+}
+
+@test "blame with line numbers, ranges and conflicts" {
+    make_single_file_repo a toto
+    pijul clone a b
+
+    echo "from a" >> a/toto
+    echo "from b" >> b/toto
+    pijul record --repository a -a -m "patch a" -A alice
+    pijul record --repository b -a -m "patch b" -A bob
+    pijul pull -a a b
+
+    cd b
+    run pijul blame toto
+    assert_success '^ *1: '
+    assert_success 'alice .*\(side [12]\) > from a'
+    assert_success 'bob .*\(side [12]\) > from b'
+
+    run pijul blame toto --porcelain
+    assert_success 'author bob'
+    assert_success 'name patch b'
+    assert_success 'conflict 1 side [12]'
+    assert_success $'\tfrom a'
+
+    run pijul blame toto --json -L 1,1
+    assert_success '"line": 1'
+    ! echo "$output" | grep --quiet '"line": 2'
+
+    run pijul blame toto -L 3,1
+    assert_failure 'Invalid line range'
+}
+
+@test "pull and push selected patches" {
+    mkdir a
+    pijul init a
+    mkdir a/sub a/other
+    echo x > a/sub/x.txt
+    pijul add --repository a sub/x.txt
+    pijul record --repository a -a -m "add sub" -A alice
+    echo y > a/other/y.txt
+    pijul add --repository a other/y.txt
+    pijul record --repository a -a -m "add other" -A bob
+    echo xx >> a/sub/x.txt
+    pijul record --repository a -a -m "edit sub" -A bob
+
+    mkdir b
+    pijul init b
+    pijul pull a b --path sub
+    assert_file_contains b/sub/x.txt xx
+    [ ! -e b/other ]
+
+    mkdir c
+    pijul init c
+    pijul pull a c --author bob --name other
+    assert_file_contains c/other/y.txt y
+    [ ! -e c/sub ]
+
+    # Pushing the edit also pushes the patch adding the file.
+    mkdir d
+    pijul init d
+    pijul push d a --name "edit sub"
+    run pijul changes --repository d --format one-line
+    assert_success 'add sub'
+    assert_success 'edit sub'
+    [[ ! "$output" =~ "add other" ]]
+}
+
+@test "named remotes" {
+    make_single_file_repo a toto
+    pijul fork --repository a dev
+    mkdir b
+    pijul init b
+    pijul remote --repository b add upstream ../a --pull-branch dev
+    pijul remote --repository b add mirror ../c
+    run pijul remote --repository b add mirror ../d
+    assert_failure 'already exists'
+    run pijul remote --repository b list
+    assert_success 'upstream ../a pull-branch=dev'
+    assert_success 'mirror ../c'
+
+    pijul pull -a upstream b
+    assert_files_equal a/toto b/toto
+
+    pijul remote --repository b rename upstream origin
+    run pijul pull -a upstream b
+    assert_failure
+    pijul remote --repository b remove origin
+    run pijul remote --repository b list
+    [[ ! "$output" =~ "origin" ]]
+    run pijul remote --repository b remove origin
+    assert_failure 'No such remote'
+}
+
+@test "remote status" {
+    make_single_file_repo a toto
+    mkdir b
+    pijul init b
+    pijul remote --repository b add upstream ../a
+    run pijul remote --repository b status
+    assert_success 'upstream \(master\): never pulled from or pushed to'
+
+    pijul pull -a upstream b
+    run pijul remote --repository b status
+    assert_success 'upstream \(master\): up to date'
+
+    append_random a/toto
+    pijul record --repository a -a -m "edit in a" -A me
+    run pijul remote --repository b status
+    assert_success 'up to date'
+    run pijul remote --repository b status --fetch
+    assert_success 'upstream \(master\): 0 ahead, 1 behind'
+
+    pijul pull -a upstream b
+    append_random b/toto
+    pijul record --repository b -a -m "edit in b" -A me
+    run pijul remote --repository b status upstream
+    assert_success '1 ahead, 0 behind'
+    pijul push -a upstream b
+    run pijul remote --repository b status upstream
+    assert_success 'up to date'
+}
+
+@test "push records the remote timestamps" {
+    make_single_file_repo a toto
+    mkdir b
+    pijul init b
+    pijul pull -a a b
+    append_random b/toto
+    pijul record --repository b -a -m "first in b" -A me
+    append_random b/toto
+    pijul record --repository b -a -m "second in b" -A me
+    pijul push -a a b
+    remote=$(pijul changes --repository a --hash-only | grep : | sort)
+    known=$(cat b/.pijul/remotes/*/* | sort)
+    [ "$remote" = "$known" ]
+}
+
+@test "incremental changes" {
+    make_single_file_repo a toto
+    append_random a/toto
+    pijul record --repository a -a -m "second" -A me
+    run pijul changes --repository a --hash-only --applied-after 0
+    assert_success '#patches 2'
+    [ "$(echo "$output" | grep -c ':1$')" -eq 1 ]
+    [ "$(echo "$output" | grep -c ':0$')" -eq 0 ]
+
+    mkdir b
+    pijul init b
+    pijul pull -a a b
+    append_random a/toto
+    pijul record --repository a -a -m "third" -A me
+    pijul pull -a a b
+    assert_files_equal a/toto b/toto
+
+    # Unrecording on the remote falls back to the full list.
+    hash=$(pijul changes --repository a --hash-only | tail -n 1 | cut -d: -f1)
+    pijul unrecord --repository a --patch "$hash"
+    run pijul remote --repository b status --fetch a
+    assert_success '1 ahead, 0 behind'
+}
+
+@test "pull from and push to pijul serve" {
+    make_single_file_repo a toto
+    echo secret > tokens
+    pijul serve --repository a --address 127.0.0.1:8765 --token-file tokens > /dev/null 2>&1 3>&- &
+    server=$!
+    sleep 1
+
+    mkdir b
+    pijul init b
+    pijul pull -a http://127.0.0.1:8765 b
+    assert_files_equal a/toto b/toto
+
+    append_random b/toto
+    pijul record --repository b -a -m "from b" -A me
+    run pijul push -a http://127.0.0.1:8765 b
+    assert_failure "401"
+    PIJUL_HTTP_TOKEN=secret pijul push -a http://127.0.0.1:8765 b
+    kill $server
+
+    run pijul changes --repository a --format one-line
+    assert_success "from b"
+}
+
+@test "pijul serve refuses large requests" {
+    make_single_file_repo a toto
+    echo secret > tokens
+    pijul serve --repository a --address 127.0.0.1:8766 --token-file tokens --max-body-size 10 \
+          > /dev/null 2>&1 3>&- &
+    server=$!
+    sleep 1
+
+    pijul clone a b
+    append_random b/toto
+    pijul record --repository b -a -m "from b" -A me
+    PIJUL_HTTP_TOKEN=secret run pijul push -a http://127.0.0.1:8766 b
+    kill $server
+    assert_failure "413"
+}
+
+@test "attributes" {
+    make_repo a
+    cd a
+    printf '*.txt eol=crlf\ndata.txt binary\n' > .pijulattributes
+    printf 'a\r\nb\r\n' > file.txt
+    printf 'x\ny\n' > data.txt
+    pijul add .pijulattributes file.txt data.txt
+    pijul record -a -m "first" -A me
+
+    # Line endings are recorded as LF, so converting them is not a change.
+    printf 'a\nb\n' > file.txt
+    run pijul status
+    assert_success "Nothing to record"
+
+    pijul fork other
+    pijul checkout master
+    printf 'a\r\nb\r\n' > expected
+    assert_files_equal expected file.txt
+    # Binary files are not converted.
+    printf 'x\ny\n' > expected
+    assert_files_equal expected data.txt
+}
+
+@test "end-of-line policy" {
+    make_repo a
+    cd a
+    printf 'default_authors = []\neol = "crlf"\n' > .pijul/meta.toml
+    printf 'a\r\nb\r\n' > file.txt
+    pijul add file.txt
+    pijul record -a -m "first" -A me
+    printf 'a\nb\n' > file.txt
+    run pijul status
+    assert_success "Nothing to record"
+
+    # The attributes file takes precedence over the configuration.
+    echo 'raw.txt eol=none' > .pijulattributes
+    printf 'a\r\n' > raw.txt
+    pijul add .pijulattributes raw.txt
+    pijul record -a -m "second" -A me
+    printf 'a\n' > raw.txt
+    run pijul status --short
+    assert_success "M raw.txt"
+    printf 'a\r\n' > raw.txt
+
+    pijul fork other
+    pijul checkout master
+    printf 'a\r\nb\r\n' > expected
+    assert_files_equal expected file.txt
+    printf 'a\r\n' > expected
+    assert_files_equal expected raw.txt
+}
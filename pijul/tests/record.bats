@@ -43,3 +43,140 @@ load test_helper
     assert_success AUTHOR.*AUTHOR
     [[ -f .pijul/meta.toml ]]
 }
+
+@test "record restricted to a path" {
+    pijul init
+    mkdir dir
+    echo a > dir/a.txt
+    echo b > b.txt
+    pijul add dir/a.txt b.txt
+    pijul record dir -a -m "only dir" -A me
+    run pijul diff
+    assert_success 'b.txt'
+    [[ ! "$output" =~ "a.txt" ]]
+
+    echo aa >> dir/a.txt
+    echo bb >> b.txt
+    run pijul diff dir/a.txt
+    assert_success 'aa'
+    [[ ! "$output" =~ "bb" ]]
+
+    pijul record b.txt -a -m "only b" -A me
+    run pijul diff
+    assert_success 'aa'
+    [[ ! "$output" =~ "bb" ]]
+}
+
+@test "unchanged stamps skip files unless --full-check" {
+    pijul init
+    echo aaa > file.txt
+    pijul add file.txt
+    pijul record -a -m "first" -A me
+    touch -d "2000-01-01" file.txt
+    run pijul diff
+    assert_success
+    [[ ! "$output" =~ "aaa" ]]
+
+    # Same size, same inode, same mtime: the stamp still matches.
+    echo bbb > file.txt
+    touch -d "2000-01-01" file.txt
+    run pijul diff
+    assert_success
+    [[ ! "$output" =~ "bbb" ]]
+
+    run pijul diff --full-check
+    assert_success "bbb"
+
+    touch file.txt
+    run pijul diff
+    assert_success "bbb"
+}
+
+@test "record edits to a large file" {
+    mkdir a
+    pijul init a
+    seq 1 5000 > a/big.txt
+    pijul add --repository a big.txt
+    pijul record --repository a -a -m "big" -A me
+    sed -i -e '10d' -e '2000s/.*/changed/' -e '4000a\
+inserted' a/big.txt
+    pijul record --repository a -a -m "edit big" -A me
+    run pijul diff --repository a
+    [[ ! "$output" =~ "changed" ]]
+    run pijul changes --repository a --hash-only
+    [ "$(echo "$output" | grep -c ':')" -eq 2 ]
+
+    mkdir b
+    pijul init b
+    pijul pull -a a b
+    assert_files_equal a/big.txt b/big.txt
+}
+
+@test "record with the patience diff" {
+    mkdir a
+    pijul init a
+    printf 'default_authors = []\ndiff_algorithm = "patience"\n' > a/.pijul/meta.toml
+    printf 'fn a() {\n    x\n}\n\nfn b() {\n    y\n}\n' > a/file.rs
+    pijul add --repository a file.rs
+    pijul record --repository a -a -m "first" -A me
+    printf 'fn a() {\n    x\n}\n\nfn c() {\n    z\n}\n\nfn b() {\n    y\n}\n' > a/file.rs
+    pijul record --repository a -a -m "add c" -A me
+    run pijul diff --repository a
+    [[ ! "$output" =~ "fn c" ]]
+
+    mkdir b
+    pijul init b
+    pijul pull -a a b
+    assert_files_equal a/file.rs b/file.rs
+}
+
+@test "record edits to a binary file" {
+    mkdir a
+    pijul init a
+    (printf '\0'; head -c 1000000 /dev/urandom) > a/file.bin
+    pijul add --repository a file.bin
+    pijul record --repository a -a -m "binary" -A me
+    printf 'edit' | dd of=a/file.bin bs=1 seek=500000 conv=notrunc
+    pijul record --repository a -a -m "edit binary" -A me
+    # Only the first patch contains the whole file.
+    [ "$(find a/.pijul/patches -size +100k | wc -l)" -eq 1 ]
+
+    mkdir b
+    pijul init b
+    pijul pull -a a b
+    assert_files_equal a/file.bin b/file.bin
+}
+
+@test "conflicting edits to a binary file" {
+    mkdir a
+    pijul init a
+    (printf '\0'; head -c 200000 /dev/urandom) > a/file.bin
+    pijul add --repository a file.bin
+    pijul record --repository a -a -m "binary" -A me
+    mkdir b
+    pijul init b
+    pijul pull -a a b
+    printf 'edit in a' | dd of=a/file.bin bs=1 seek=100000 conv=notrunc
+    pijul record --repository a -a -m "edit in a" -A me
+    printf 'edit in b' | dd of=b/file.bin bs=1 seek=100000 conv=notrunc
+    pijul record --repository b -a -m "edit in b" -A me
+
+    mkdir c
+    pijul init c
+    pijul pull -a a c
+    pijul pull -a b c
+    # No markers, only one of the two versions.
+    cmp c/file.bin a/file.bin || cmp c/file.bin b/file.bin
+}
+
+@test "diff does not create the branch it compares with" {
+    pijul init
+    echo aaa > file.txt
+    pijul add file.txt
+    pijul record -a -m "first" -A me
+    run pijul diff --branch other
+    assert_success "aaa"
+    run pijul branches
+    assert_success
+    [[ ! "$output" =~ "other" ]]
+}
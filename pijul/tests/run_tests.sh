@@ -4,3 +4,6 @@ cargo build
 cd `dirname "$0"`
 ./bats/bats pijul.bats
 ./bats/bats record.bats
+./bats/bats ignore.bats
+./bats/bats signature.bats
+./bats/bats changes.bats
@@ -0,0 +1,63 @@
+#!/usr/bin/env bats
+
+load test_helper
+
+set_policy() {
+    printf 'default_authors = []\nsignature_policy = "%s"\n' "$2" > "$1"/.pijul/meta.toml
+}
+
+@test "record --sign needs a key" {
+    make_repo a
+    touch a/file.txt
+    pijul add --repository a file.txt
+    run pijul record -a --repository a -m msg -A me --sign
+    assert_failure "No signing key"
+}
+
+@test "pull signed patches" {
+    pijul key generate
+    make_repo a
+    make_random_file a/file.txt
+    pijul add --repository a file.txt
+    pijul record -a --repository a -m msg -A me --sign
+    make_repo b
+    set_policy b reject-unknown
+    run pijul pull -a a b
+    assert_success
+    assert_files_equal a/file.txt b/file.txt
+}
+
+@test "reject unsigned patches" {
+    make_single_file_repo a file.txt
+    make_repo b
+    set_policy b reject-unsigned
+    run pijul pull -a a b
+    assert_failure "is not signed"
+    [[ ! -f b/file.txt ]]
+
+    set_policy b warn
+    run pijul pull -a a b
+    assert_success "Warning: patch .* is not signed"
+    assert_files_equal a/file.txt b/file.txt
+}
+
+@test "reject patches signed by unknown keys" {
+    pijul key generate
+    make_repo a
+    make_random_file a/file.txt
+    pijul add --repository a file.txt
+    pijul record -a --repository a -m msg -A me --sign
+    public_key=`pijul key show`
+
+    # Replace our key, so that the patch's key becomes unknown.
+    pijul key generate
+    make_repo b
+    set_policy b reject-unknown
+    run pijul pull -a a b
+    assert_failure "unknown key"
+
+    pijul key trust alice "$public_key"
+    run pijul pull -a a b
+    assert_success
+    assert_files_equal a/file.txt b/file.txt
+}